@@ -0,0 +1,101 @@
+use crate::convert::{FileValues, ParseSettings};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Values read from a yaml file, along with the file metadata at the time it was read
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheEntry {
+    modified: SystemTime,
+    size: u64,
+    values: FileValues,
+}
+
+/// Manifest of previously parsed yaml files, persisted between runs
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct ParseCache {
+    /// The settings the cached files were read with
+    #[serde(default)]
+    settings: ParseSettings,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+/// Read the modification time and size of a file
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Let only the owner of a file read and write it
+#[cfg(unix)]
+fn restrict_to_owner(file: &File) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn restrict_to_owner(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+impl ParseCache {
+    /// Create an empty cache for files read with `settings`
+    pub fn new(settings: ParseSettings) -> Self {
+        Self {
+            settings,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Load the cache from disk, starting empty if it does not exist yet or was written with
+    /// other settings
+    pub fn load(path: &Path, settings: ParseSettings) -> serde_json::Result<Self> {
+        match File::open(path) {
+            Ok(file) => {
+                let cache: Self = serde_json::from_reader(file)?;
+                if cache.settings == settings {
+                    Ok(cache)
+                } else {
+                    Ok(Self::new(settings))
+                }
+            }
+            Err(_) => Ok(Self::new(settings)),
+        }
+    }
+
+    /// Write the cache to disk. It holds the values of the yaml files, so on unix only its
+    /// owner may read it, whatever its mode was.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        restrict_to_owner(&file)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Get the cached values of a file, if it is unchanged since it was cached
+    pub fn get(&self, path: &Path) -> Option<&FileValues> {
+        let entry = self.entries.get(path)?;
+        let (modified, size) = file_stamp(path)?;
+        if entry.modified == modified && entry.size == size {
+            Some(&entry.values)
+        } else {
+            None
+        }
+    }
+
+    /// Store the values read from a file
+    pub fn insert(&mut self, path: &Path, values: FileValues) {
+        if let Some((modified, size)) = file_stamp(path) {
+            self.entries.insert(
+                path.to_path_buf(),
+                CacheEntry {
+                    modified,
+                    size,
+                    values,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use crate::YamlToEnvError;
+use clap::ArgEnum;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// How the config file lists the yaml files
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// One path per line
+    Lines,
+    /// A JSON array of paths
+    Json,
+    /// A yaml sequence of paths
+    Yaml,
+    /// A directory whose yaml files are all read, in name order
+    Dir,
+}
+
+/// A path listed by the config, with the key prefix declared for its files
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigEntry {
+    pub path: PathBuf,
+    pub key_prefix: Option<String>,
+}
+
+impl ConfigEntry {
+    /// Create an entry for a path without a key prefix
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            key_prefix: None,
+        }
+    }
+}
+
+/// The yaml files a config resolves to, along with the key prefixes it declares for them
+#[derive(Clone, Debug, Default)]
+pub struct ResolvedPaths {
+    pub paths: Vec<PathBuf>,
+    pub file_prefixes: HashMap<PathBuf, String>,
+}
+
+/// Expand a leading environment variable in a config path, e.g. `$CONFIG_ROOT/app.yaml`
+fn expand_config_path(entry: &str) -> Result<PathBuf, YamlToEnvError> {
+    let rest = match entry.strip_prefix('$') {
+        Some(rest) => rest,
+        None => return Ok(PathBuf::from(entry)),
+    };
+    let (var_name, remainder) = match rest.strip_prefix('{') {
+        Some(braced) => {
+            braced
+                .split_once('}')
+                .ok_or_else(|| YamlToEnvError::UnclosedPathVariable {
+                    entry: String::from(entry),
+                })?
+        }
+        None => rest.split_at(
+            rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len()),
+        ),
+    };
+    let root = std::env::var(var_name).map_err(|_| YamlToEnvError::UnsetPathVariable {
+        var_name: String::from(var_name),
+        entry: String::from(entry),
+    })?;
+    Ok(PathBuf::from(format!("{}{}", root, remainder)))
+}
+
+/// Parse a config entry, which may declare a key prefix after an arrow, e.g.
+/// `payments.yaml -> PAYMENTS_`
+pub fn parse_config_entry(entry: &str) -> Result<ConfigEntry, YamlToEnvError> {
+    let (path, key_prefix) = match entry.split_once("->") {
+        Some((path, key_prefix)) => (path.trim(), Some(String::from(key_prefix.trim()))),
+        None => (entry, None),
+    };
+    Ok(ConfigEntry {
+        path: expand_config_path(path)?,
+        key_prefix,
+    })
+}
+
+/// Read the entries listed by the config file, interpreted per `config_format`. Lines longer
+/// than `max_path_length` bytes are rejected without being buffered whole.
+pub fn read_config_file(
+    path: &Path,
+    config_format: ConfigFormat,
+    max_path_length: usize,
+) -> Result<Vec<ConfigEntry>, YamlToEnvError> {
+    let unreadable_config = || YamlToEnvError::UnreadableConfig {
+        path: path.to_path_buf(),
+    };
+    match config_format {
+        ConfigFormat::Dir => {
+            return Ok(read_config_dir(path)?
+                .into_iter()
+                .map(ConfigEntry::new)
+                .collect())
+        }
+        ConfigFormat::Lines => {
+            let file = File::open(path).map_err(|_| unreadable_config())?;
+            return read_config_lines(BufReader::new(file), path, max_path_length)?
+                .iter()
+                .map(|entry| parse_config_entry(entry))
+                .collect();
+        }
+        _ => {}
+    }
+
+    let content = std::fs::read_to_string(path).map_err(|_| unreadable_config())?;
+    let invalid_config = |reason: String| YamlToEnvError::InvalidConfig {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let entries = match config_format {
+        ConfigFormat::Json => serde_json::from_str::<Vec<String>>(&content)
+            .map_err(|err| invalid_config(err.to_string()))?,
+        ConfigFormat::Yaml => serde_yaml::from_str::<Vec<String>>(&content)
+            .map_err(|err| invalid_config(err.to_string()))?,
+        ConfigFormat::Lines | ConfigFormat::Dir => unreachable!(),
+    };
+    entries
+        .iter()
+        .map(|entry| parse_config_entry(entry))
+        .collect()
+}
+
+/// Read the lines of a config file one at a time, never buffering more than
+/// `max_path_length` bytes of a line
+fn read_config_lines(
+    mut reader: impl BufRead,
+    path: &Path,
+    max_path_length: usize,
+) -> Result<Vec<String>, YamlToEnvError> {
+    let unreadable_config = || YamlToEnvError::UnreadableConfig {
+        path: path.to_path_buf(),
+    };
+    let mut lines = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        // room for the path and a trailing "\r\n"
+        let limit = max_path_length as u64 + 2;
+        let read = (&mut reader)
+            .take(limit)
+            .read_until(b'\n', &mut buf)
+            .map_err(|_| unreadable_config())?;
+        if read == 0 {
+            return Ok(lines);
+        }
+        if buf.ends_with(b"\n") {
+            buf.pop();
+            if buf.ends_with(b"\r") {
+                buf.pop();
+            }
+        }
+        if buf.len() > max_path_length {
+            return Err(YamlToEnvError::ConfigLineTooLong {
+                line_number: lines.len() + 1,
+                max_path_length,
+            });
+        }
+        lines.push(String::from_utf8(std::mem::take(&mut buf)).map_err(|_| unreadable_config())?);
+    }
+}
+
+/// List the yaml files directly inside a config directory, sorted by name
+fn read_config_dir(path: &Path) -> Result<Vec<PathBuf>, YamlToEnvError> {
+    let mut paths = std::fs::read_dir(path)
+        .map_err(|_| YamlToEnvError::UnreadableConfig {
+            path: path.to_path_buf(),
+        })?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && has_yaml_extension(path))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Parse the entries listed inline, separated by commas or newlines
+pub fn parse_inline_config(config: &str) -> Result<Vec<ConfigEntry>, YamlToEnvError> {
+    config
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(parse_config_entry)
+        .collect()
+}
+
+/// Whether a path has the `.yaml` or `.yml` extension
+pub fn has_yaml_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "yaml" || ext == "yml")
+}
+
+/// Add the yaml files found anywhere inside a directory to `paths`, sorted by name
+fn find_yaml_files(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), YamlToEnvError> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|err| YamlToEnvError::UnreadableDir {
+            path: dir.to_path_buf(),
+            reason: err.to_string(),
+        })?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<PathBuf>>();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            find_yaml_files(&entry, paths)?;
+        } else if has_yaml_extension(&entry) {
+            paths.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Replace a directory by the yaml files inside it and a glob pattern by the paths it
+/// matches, leaving URLs and other paths as they are
+fn expand_input_path(path: PathBuf) -> Result<Vec<PathBuf>, YamlToEnvError> {
+    let path_str = path.to_string_lossy();
+    if path_str.starts_with("http://") || path_str.starts_with("https://") {
+        Ok(vec![path])
+    } else if path_str.contains(['*', '?', '[']) {
+        let mut matches = glob::glob(&path_str)
+            .map_err(|err| YamlToEnvError::InvalidGlob {
+                pattern: path_str.to_string(),
+                reason: err.to_string(),
+            })?
+            .filter_map(Result::ok)
+            .collect::<Vec<PathBuf>>();
+        if matches.is_empty() {
+            return Err(YamlToEnvError::NoGlobMatch {
+                pattern: path_str.to_string(),
+            });
+        }
+        matches.sort();
+        Ok(matches)
+    } else if path.is_dir() {
+        let mut paths = Vec::new();
+        find_yaml_files(&path, &mut paths)?;
+        Ok(paths)
+    } else {
+        Ok(vec![path])
+    }
+}
+
+/// Expand the globs and directories of the config entries into the yaml files to read.
+/// Paths pointing to a file already listed, e.g. `./a.yaml` after `a.yaml` or a symlink to
+/// it, are dropped, keeping the first spelling. Fails if a path is not a yaml file or there
+/// are more than `max_files` of them.
+pub fn resolve_config_entries(
+    entries: Vec<ConfigEntry>,
+    max_files: Option<usize>,
+) -> Result<ResolvedPaths, YamlToEnvError> {
+    let mut resolved = ResolvedPaths::default();
+    let mut seen_files = HashSet::new();
+    for entry in entries {
+        for path in expand_input_path(entry.path)? {
+            if !seen_files.insert(std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone())) {
+                continue;
+            }
+            if let Some(key_prefix) = &entry.key_prefix {
+                resolved
+                    .file_prefixes
+                    .insert(path.clone(), key_prefix.clone());
+            }
+            resolved.paths.push(path);
+        }
+    }
+    match max_files {
+        Some(max_files) if resolved.paths.len() > max_files => {
+            return Err(YamlToEnvError::TooManyFiles {
+                file_count: resolved.paths.len(),
+                max_files,
+            })
+        }
+        _ => {}
+    }
+    if let Some(path) = resolved.paths.iter().find(|path| !has_yaml_extension(path)) {
+        return Err(YamlToEnvError::NotYamlFile { path: path.clone() });
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn expands_a_set_root_variable() {
+        std::env::set_var("YAML_TO_ENV_TEST_ROOT", "/etc/app");
+        let entry = parse_config_entry("$YAML_TO_ENV_TEST_ROOT/app.yaml").unwrap();
+        assert_eq!(entry.path, PathBuf::from("/etc/app/app.yaml"));
+        let entry = parse_config_entry("${YAML_TO_ENV_TEST_ROOT}/db.yaml").unwrap();
+        assert_eq!(entry.path, PathBuf::from("/etc/app/db.yaml"));
+    }
+
+    #[test]
+    fn fails_on_an_unset_root_variable() {
+        std::env::remove_var("YAML_TO_ENV_TEST_UNSET_ROOT");
+        let err = parse_config_entry("$YAML_TO_ENV_TEST_UNSET_ROOT/app.yaml").unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::UnsetPathVariable { var_name, .. }
+                if var_name == "YAML_TO_ENV_TEST_UNSET_ROOT"
+        ));
+    }
+
+    #[test]
+    fn lists_a_file_given_under_two_spellings_once() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "key: 1\n");
+        let other_spelling = path.parent().unwrap().join(".").join("a.yaml");
+        let entries = vec![ConfigEntry::new(&path), ConfigEntry::new(other_spelling)];
+
+        let resolved = resolve_config_entries(entries, None).unwrap();
+        assert_eq!(resolved.paths, vec![path]);
+    }
+
+    #[test]
+    fn expands_a_glob_into_the_sorted_paths_it_matches() {
+        let dir = TempDir::new();
+        let b = dir.write("b.yaml", "");
+        let a = dir.write("a.yaml", "");
+        dir.write("c.yml", "");
+        let pattern = a.parent().unwrap().join("*.yaml");
+        let entries = vec![ConfigEntry::new(&pattern), ConfigEntry::new(&b)];
+
+        let resolved = resolve_config_entries(entries, None).unwrap();
+        assert_eq!(resolved.paths, vec![a, b]);
+    }
+
+    #[test]
+    fn fails_on_a_glob_matching_nothing() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "");
+        let pattern = path.parent().unwrap().join("*.yml");
+        let entries = vec![ConfigEntry::new(&pattern)];
+
+        let err = resolve_config_entries(entries, None).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::NoGlobMatch { pattern: err_pattern }
+                if err_pattern == pattern.to_string_lossy()
+        ));
+    }
+
+    #[test]
+    fn reads_the_yaml_files_inside_a_directory() {
+        let dir = TempDir::new();
+        let b = dir.write("b.yml", "");
+        let a = dir.write("a.yaml", "");
+        dir.write("notes.txt", "");
+        let root = a.parent().unwrap();
+        std::fs::create_dir(root.join("nested")).unwrap();
+        let nested = dir.write("nested/c.yaml", "");
+        let entries = vec![ConfigEntry::new(root)];
+
+        let resolved = resolve_config_entries(entries, None).unwrap();
+        assert_eq!(resolved.paths, vec![a, b, nested]);
+    }
+
+    #[test]
+    fn reads_a_config_in_the_format_asked_for_whatever_its_name() {
+        let dir = TempDir::new();
+        let path = dir.write("files.txt", r#"["a.yaml", "b.yaml -> B_"]"#);
+
+        let entries = read_config_file(&path, ConfigFormat::Json, 4096).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ConfigEntry::new("a.yaml"),
+                ConfigEntry {
+                    path: PathBuf::from("b.yaml"),
+                    key_prefix: Some(String::from("B_")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_a_directory_expanding_to_more_files_than_the_limit() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "");
+        dir.write("b.yaml", "");
+        dir.write("c.yaml", "");
+        let entries = vec![ConfigEntry::new(path.parent().unwrap())];
+
+        assert_eq!(
+            resolve_config_entries(entries.clone(), Some(3))
+                .unwrap()
+                .paths
+                .len(),
+            3
+        );
+        let err = resolve_config_entries(entries, Some(2)).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::TooManyFiles {
+                file_count: 3,
+                max_files: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn fails_on_a_config_line_longer_than_the_bound() {
+        let dir = TempDir::new();
+        let path = dir.write("files.txt", &format!("a.yaml\n{}\n", "x".repeat(1 << 20)));
+
+        let err = read_config_file(&path, ConfigFormat::Lines, 4096).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::ConfigLineTooLong {
+                line_number: 2,
+                max_path_length: 4096
+            }
+        ));
+    }
+}
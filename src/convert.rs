@@ -0,0 +1,3432 @@
+use crate::cache::ParseCache;
+use crate::dotenv::{parse_env_str, quote_dotenv_value};
+use crate::explain::KeyTrace;
+use crate::interpolate::interpolate_values;
+use crate::{EnvMap, Warning, YamlToEnvError};
+use clap::ArgEnum;
+use indexmap::IndexMap;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::value::TaggedValue;
+use serde_yaml::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+
+/// Format of the generated output
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// `KEY=value` lines
+    #[clap(alias = "dotenv")]
+    Env,
+    /// `export KEY="value"` lines, to be read with `source`
+    Shell,
+    /// `-e 'KEY=value'` arguments for `docker run`, on a single line
+    Docker,
+    /// One `{"key":"KEY","value":"value"}` JSON object per line, sorted by key
+    Jsonl,
+    /// `KEY = "value"` HCL attribute assignments, sorted by key
+    Hcl,
+    /// A JSON object, sorted by key
+    Json,
+    /// A TOML table, sorted by key
+    Toml,
+    /// A YAML mapping sorted by key, the variables nested under the mappings and sequences
+    /// of the yaml files they were flattened from if their layout is known, see
+    /// `FormatOptions::yaml_layout`, and written flat otherwise
+    Yaml,
+}
+
+/// When to wrap values of the env format in double quotes
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuoteMode {
+    /// Quote values whose spaces, `#`, `=`, quotes or newlines .env parsers would misread
+    Auto,
+    /// Quote every value
+    Always,
+    /// Write values as they are
+    Never,
+}
+
+/// What to do when a yaml file cannot be read or parsed
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnParseError {
+    /// Stop without writing any output
+    Abort,
+    /// Skip the file with a warning, write the output of the other files and exit non-zero
+    Continue,
+}
+
+/// What to do when several yaml files set the same key
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Stop, naming both files
+    Error,
+    /// Keep the value of the later file with a warning naming both files
+    WarnLastWins,
+    /// Keep the value of the earlier file
+    FirstWins,
+    /// Keep the value of the later file
+    LastWins,
+}
+
+/// Version of the yaml spec deciding which plain scalars are booleans
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YamlVersion {
+    /// `yes`, `no`, `on`, `off`, `y` and `n` are booleans too, written as `true` or `false`
+    #[clap(name = "1.1")]
+    V1_1,
+    /// Only `true` and `false` are booleans, everything else is kept as written
+    #[clap(name = "1.2")]
+    V1_2,
+}
+
+/// How yaml sequences are written as env variables
+#[derive(ArgEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArrayMode {
+    /// One variable per item, the key suffixed with the item index, e.g. `HOSTS_0`
+    #[default]
+    Index,
+    /// One variable holding the items joined with commas, e.g. `HOSTS=a,b`. Sequences holding
+    /// mappings or sequences are indexed instead.
+    Join,
+    /// One variable holding the sequence as compact JSON, e.g. `HOSTS=["a","b"]`
+    Json,
+}
+
+/// Casing applied to the keys read from the yaml files, before any file prefix
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCase {
+    /// `dbHost` and `db-host` both become `DB_HOST`, and `HTTPServer` becomes `HTTP_SERVER`
+    UpperSnake,
+    /// Keys are kept as written
+    Preserve,
+    /// `DB_HOST` becomes `db_host`
+    Lower,
+}
+
+impl KeyCase {
+    /// Apply the casing to a key
+    fn apply(self, key: &str) -> String {
+        match self {
+            KeyCase::UpperSnake => {
+                let chars = key.chars().collect::<Vec<char>>();
+                let mut converted = String::new();
+                for (i, &c) in chars.iter().enumerate() {
+                    let previous = i.checked_sub(1).map(|i| chars[i]);
+                    let next = chars.get(i + 1);
+                    // a word starts after a lower case letter or a digit, and at the last upper
+                    // case letter of an acronym, e.g. the `S` of `HTTPServer`
+                    let starts_word = previous.is_some_and(|p| {
+                        p.is_lowercase()
+                            || p.is_ascii_digit()
+                            || p.is_uppercase() && next.is_some_and(|n| n.is_lowercase())
+                    });
+                    if c.is_uppercase() && starts_word {
+                        converted.push('_');
+                    }
+                    match c {
+                        '-' | ' ' => converted.push('_'),
+                        c => converted.extend(c.to_uppercase()),
+                    }
+                }
+                converted
+            }
+            KeyCase::Preserve => String::from(key),
+            KeyCase::Lower => key.to_lowercase(),
+        }
+    }
+}
+
+/// Settings of the output formatting, built with chained setters starting from
+/// `FormatOptions::new()`
+#[derive(Clone, Debug)]
+pub struct FormatOptions {
+    output_format: OutputFormat,
+    sort: bool,
+    quote_mode: QuoteMode,
+    pretty: bool,
+    dotenv_compat: bool,
+    quoted_strings: HashSet<String>,
+    yaml_layout: Option<YamlLayout>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            output_format: OutputFormat::Env,
+            sort: false,
+            quote_mode: QuoteMode::Auto,
+            pretty: false,
+            dotenv_compat: false,
+            quoted_strings: HashSet::new(),
+            yaml_layout: None,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// Create options with the defaults of the command line tool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The format of the output
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// Sort the variables of the env, shell and docker formats by key instead of keeping the
+    /// order they were read in. The other formats are always sorted.
+    pub fn sort(mut self, sort: bool) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// When to wrap values of the env format in double quotes, unless `dotenv_compat` is set
+    pub fn quote_mode(mut self, quote_mode: QuoteMode) -> Self {
+        self.quote_mode = quote_mode;
+        self
+    }
+
+    /// Pretty-print the json output format instead of writing it compactly
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.pretty = pretty;
+        self
+    }
+
+    /// Quote values in the env format so the JS dotenv library reads them back unchanged,
+    /// see `quote_dotenv_value` for the rules
+    pub fn dotenv_compat(mut self, dotenv_compat: bool) -> Self {
+        self.dotenv_compat = dotenv_compat;
+        self
+    }
+
+    /// Keys whose values the env format writes in double quotes whatever the quote mode, e.g.
+    /// those of yaml strings like `port: "8080"`
+    pub fn quoted_strings(mut self, quoted_strings: HashSet<String>) -> Self {
+        self.quoted_strings = quoted_strings;
+        self
+    }
+
+    /// The layout of the yaml files the yaml format nests the variables by, e.g. the
+    /// `yaml_layout` of `Parsed`
+    pub fn yaml_layout(mut self, yaml_layout: Option<YamlLayout>) -> Self {
+        self.yaml_layout = yaml_layout;
+        self
+    }
+
+    /// Whether newlines in values are written as they are, i.e. unquoted in the env format,
+    /// the other formats and quoting modes escaping or quoting them
+    fn writes_raw_newlines(&self) -> bool {
+        self.output_format == OutputFormat::Env
+            && !self.dotenv_compat
+            && self.quote_mode == QuoteMode::Never
+    }
+}
+
+/// Settings of a conversion, built with chained setters starting from `ConvertOptions::new()`
+#[derive(Clone, Debug)]
+pub struct ConvertOptions {
+    allow_multiline: bool,
+    unquote_numbers: bool,
+    keep_string_quotes: bool,
+    url_decode: bool,
+    url_decode_strict: bool,
+    allowlist: Option<Vec<String>>,
+    default_value: Option<String>,
+    key_pattern: Option<Regex>,
+    on_parse_error: OnParseError,
+    on_conflict: OnConflict,
+    format: FormatOptions,
+    group_by_file: bool,
+    cache_path: Option<PathBuf>,
+    changed_since: Option<EnvMap>,
+    warn_on_trim: bool,
+    prefix_from_filename: bool,
+    file_prefixes: HashMap<PathBuf, String>,
+    key_case: KeyCase,
+    case_insensitive_keys: bool,
+    timeout: Option<Duration>,
+    profiles: Vec<String>,
+    yaml_version: YamlVersion,
+    explain_key: Option<String>,
+    base_env: Option<SourceFile>,
+    separator: String,
+    array_separator: Option<String>,
+    max_depth: usize,
+    array_mode: ArrayMode,
+    env_section: Option<String>,
+    interpolate: bool,
+    allow_env_substitution: bool,
+    lenient_yaml: bool,
+    strip_trailing_value_newline: bool,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            allow_multiline: false,
+            unquote_numbers: false,
+            keep_string_quotes: false,
+            url_decode: false,
+            url_decode_strict: false,
+            allowlist: None,
+            default_value: None,
+            key_pattern: None,
+            on_parse_error: OnParseError::Abort,
+            on_conflict: OnConflict::LastWins,
+            format: FormatOptions::default(),
+            group_by_file: false,
+            cache_path: None,
+            changed_since: None,
+            warn_on_trim: false,
+            prefix_from_filename: false,
+            file_prefixes: HashMap::new(),
+            key_case: KeyCase::Preserve,
+            case_insensitive_keys: false,
+            timeout: None,
+            profiles: Vec::new(),
+            yaml_version: YamlVersion::V1_2,
+            explain_key: None,
+            base_env: None,
+            separator: String::from("_"),
+            array_separator: None,
+            max_depth: 64,
+            array_mode: ArrayMode::Index,
+            env_section: None,
+            interpolate: true,
+            allow_env_substitution: false,
+            lenient_yaml: false,
+            strip_trailing_value_newline: true,
+        }
+    }
+}
+
+impl ConvertOptions {
+    /// Create options with the defaults of the command line tool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow values spanning multiple lines without emitting a warning when they are written
+    /// unquoted, i.e. in the env format with `QuoteMode::Never`
+    pub fn allow_multiline(mut self, allow_multiline: bool) -> Self {
+        self.allow_multiline = allow_multiline;
+        self
+    }
+
+    /// Write yaml strings holding a number without quotes in the env format, e.g. `port: "8080"`
+    /// becomes `port=8080` instead of `port="8080"`
+    pub fn unquote_numbers(mut self, unquote_numbers: bool) -> Self {
+        self.unquote_numbers = unquote_numbers;
+        self
+    }
+
+    /// Write the yaml strings written in quotes in double quotes in the env format, e.g.
+    /// `name: 'app'` becomes `name="app"`, whatever the quote mode
+    pub fn keep_string_quotes(mut self, keep_string_quotes: bool) -> Self {
+        self.keep_string_quotes = keep_string_quotes;
+        self
+    }
+
+    /// Percent-decode values. Values with a malformed escape are left as written, or fail if
+    /// `strict` is set.
+    pub fn url_decode(mut self, url_decode: bool, strict: bool) -> Self {
+        self.url_decode = url_decode;
+        self.url_decode_strict = strict;
+        self
+    }
+
+    /// Output only these keys, all of which must be defined by the yaml files
+    pub fn allowlist(mut self, allowlist: Option<Vec<String>>) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    /// Value given to allowlisted keys missing from the yaml files, instead of failing
+    pub fn default_value(mut self, default_value: Option<String>) -> Self {
+        self.default_value = default_value;
+        self
+    }
+
+    /// Output only the keys matching this pattern
+    pub fn key_pattern(mut self, key_pattern: Option<Regex>) -> Self {
+        self.key_pattern = key_pattern;
+        self
+    }
+
+    /// What to do when a yaml file cannot be read or parsed
+    pub fn on_parse_error(mut self, on_parse_error: OnParseError) -> Self {
+        self.on_parse_error = on_parse_error;
+        self
+    }
+
+    /// What to do when several yaml files set the same key. The base env is always overridden.
+    pub fn on_conflict(mut self, on_conflict: OnConflict) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// The format of the output
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.format = self.format.output_format(output_format);
+        self
+    }
+
+    /// Sort the variables of the env, shell and docker formats by key instead of keeping the
+    /// order they were read in. The other formats are always sorted.
+    pub fn sort(mut self, sort: bool) -> Self {
+        self.format = self.format.sort(sort);
+        self
+    }
+
+    /// When to wrap values of the env format in double quotes, unless `dotenv_compat` is set
+    pub fn quote_mode(mut self, quote_mode: QuoteMode) -> Self {
+        self.format = self.format.quote_mode(quote_mode);
+        self
+    }
+
+    /// Group variables under a comment naming their yaml file (env format only)
+    pub fn group_by_file(mut self, group_by_file: bool) -> Self {
+        self.group_by_file = group_by_file;
+        self
+    }
+
+    /// Cache file used to skip reparsing yaml files that did not change. It holds the values
+    /// read from the files in plain text, so on unix it is written readable by its owner only.
+    pub fn cache_path(mut self, cache_path: Option<PathBuf>) -> Self {
+        self.cache_path = cache_path;
+        self
+    }
+
+    /// Output only the keys that are new or changed compared to a previously generated map,
+    /// noting removed keys as comments (env format only)
+    pub fn changed_since(mut self, previous: Option<EnvMap>) -> Self {
+        self.changed_since = previous;
+        self
+    }
+
+    /// Warn when trimming whitespace on output changes a key or value
+    pub fn warn_on_trim(mut self, warn_on_trim: bool) -> Self {
+        self.warn_on_trim = warn_on_trim;
+        self
+    }
+
+    /// Prefix the keys of each file with its uppercased file name, e.g. `DATABASE_` for
+    /// `database.yaml`
+    pub fn prefix_from_filename(mut self, prefix_from_filename: bool) -> Self {
+        self.prefix_from_filename = prefix_from_filename;
+        self
+    }
+
+    /// Prefix the keys of these files with the given prefix, kept as written, instead of
+    /// the one derived from the file name
+    pub fn file_prefixes(mut self, file_prefixes: HashMap<PathBuf, String>) -> Self {
+        self.file_prefixes = file_prefixes;
+        self
+    }
+
+    /// Casing of the keys read from the yaml files
+    pub fn key_case(mut self, key_case: KeyCase) -> Self {
+        self.key_case = key_case;
+        self
+    }
+
+    /// Pretty-print the json output format instead of writing it compactly
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.format = self.format.pretty(pretty);
+        self
+    }
+
+    /// Treat keys differing only in case as the same variable, as Windows does, keeping the
+    /// casing written last. Keys are case-sensitive by default, as on Unix.
+    pub fn case_insensitive_keys(mut self, case_insensitive_keys: bool) -> Self {
+        self.case_insensitive_keys = case_insensitive_keys;
+        self
+    }
+
+    /// Time limit for fetching each yaml file given as a URL (`remote` feature), 30 seconds if
+    /// not set
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Quote values in the env format so the JS dotenv library reads them back unchanged,
+    /// see `quote_dotenv_value` for the rules
+    pub fn dotenv_compat(mut self, dotenv_compat: bool) -> Self {
+        self.format = self.format.dotenv_compat(dotenv_compat);
+        self
+    }
+
+    /// Include yaml keys marked for these profiles with a `@profile` suffix, e.g. `DEBUG@dev`,
+    /// without the suffix, merging their values over the unmarked key, later profiles winning.
+    /// Keys marked for other profiles are dropped, unmarked keys always included. Every profile
+    /// must be used by some yaml file. Keys are left as written if no profile is selected.
+    pub fn profiles(mut self, profiles: Vec<String>) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    /// The yaml spec version used to interpret plain scalars in keys and values
+    pub fn yaml_version(mut self, yaml_version: YamlVersion) -> Self {
+        self.yaml_version = yaml_version;
+        self
+    }
+
+    /// Trace how this key gets its final value through the files and transformations
+    pub fn explain_key(mut self, explain_key: Option<String>) -> Self {
+        self.explain_key = explain_key;
+        self
+    }
+
+    /// Variables of an env file to start from, the yaml files overriding them
+    pub fn base_env(mut self, base_env: Option<SourceFile>) -> Self {
+        self.base_env = base_env;
+        self
+    }
+
+    /// Separator joining the keys of nested yaml mappings, e.g. `_` turns `database: {host: x}`
+    /// into `database_host`
+    pub fn separator(mut self, separator: String) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    /// Separator joining the indices of yaml sequences to their key, e.g. `__` turns
+    /// `hosts: [a]` into `hosts__0`, instead of the mapping key separator
+    pub fn array_separator(mut self, array_separator: Option<String>) -> Self {
+        self.array_separator = array_separator;
+        self
+    }
+
+    /// Fail on yaml keys nested deeper than this many mappings and sequences, 64 by default
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// How yaml sequences are written as env variables
+    pub fn array_mode(mut self, array_mode: ArrayMode) -> Self {
+        self.array_mode = array_mode;
+        self
+    }
+
+    /// Take the variables of each yaml file from the top-level section of this name, e.g.
+    /// `production`, over those of a `default` or `common` section. Other keys are dropped
+    /// and some yaml file must have the section.
+    pub fn env_section(mut self, env_section: Option<String>) -> Self {
+        self.env_section = env_section;
+        self
+    }
+
+    /// Replace `${NAME}` in the yaml values by the value of the key `NAME`, which must be
+    /// defined, writing `$${` as `${`. Values of `!env` tags and of the base env are left as
+    /// they are.
+    pub fn interpolate(mut self, interpolate: bool) -> Self {
+        self.interpolate = interpolate;
+        self
+    }
+
+    /// Let `${NAME}` refer to the environment variable `NAME` when no key has that name
+    pub fn allow_env_substitution(mut self, allow_env_substitution: bool) -> Self {
+        self.allow_env_substitution = allow_env_substitution;
+        self
+    }
+
+    /// Fall back to the colon-splitting line parser for files that serde_yaml fails to parse,
+    /// with a warning, instead of failing
+    pub fn lenient_yaml(mut self, lenient_yaml: bool) -> Self {
+        self.lenient_yaml = lenient_yaml;
+        self
+    }
+
+    /// Drop the line break ending `|` and `>` block scalars, keeping the line breaks inside
+    /// them. Blocks kept with `|+` or `>+` and quoted strings are left as written. Set by
+    /// default.
+    pub fn strip_trailing_value_newline(mut self, strip_trailing_value_newline: bool) -> Self {
+        self.strip_trailing_value_newline = strip_trailing_value_newline;
+        self
+    }
+
+    /// The options used to format the parsed env map
+    pub fn format_options(&self) -> &FormatOptions {
+        &self.format
+    }
+
+    /// Separator joining the keys of nested yaml mappings
+    pub(crate) fn key_separator(&self) -> &str {
+        &self.separator
+    }
+
+    /// The options deciding how each yaml file is read, which cached values must match
+    fn parse_settings(&self) -> ParseSettings {
+        ParseSettings {
+            separator: self.separator.clone(),
+            array_separator: self
+                .array_separator
+                .clone()
+                .unwrap_or_else(|| self.separator.clone()),
+            max_depth: self.max_depth,
+            array_mode: self.array_mode,
+            yaml_1_1_bools: self.yaml_version == YamlVersion::V1_1,
+            env_section: self.env_section.clone(),
+            strip_trailing_value_newline: self.strip_trailing_value_newline,
+            profiles: self.profiles.clone(),
+            keep_string_quotes: self.keep_string_quotes,
+        }
+    }
+}
+
+/// Settings deciding how a yaml file is read into flattened values
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ParseSettings {
+    /// Separator joining the keys of nested mappings
+    separator: String,
+    /// Separator joining the indices of sequences
+    array_separator: String,
+    /// The number of nested keys a flattened key may be made of
+    max_depth: usize,
+    array_mode: ArrayMode,
+    /// Whether plain scalars like `yes` or `off` are booleans, as in yaml 1.1
+    yaml_1_1_bools: bool,
+    /// The top-level section the values are taken from, over a `default` or `common` one
+    env_section: Option<String>,
+    /// Whether the line break ending a block scalar is dropped
+    #[serde(default)]
+    strip_trailing_value_newline: bool,
+    /// The profiles whose `@profile` marked keys are selected, in the order they override
+    /// each other
+    #[serde(default)]
+    profiles: Vec<String>,
+    /// Whether the yaml strings written in quotes are told apart from other strings
+    #[serde(default)]
+    keep_string_quotes: bool,
+}
+
+/// A flattened yaml value, keeping how it was written where its text alone loses it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum YamlScalar {
+    /// A number, a boolean, or a value read from an env file
+    Plain(String),
+    /// A yaml string, e.g. `port: "8080"`
+    String(String),
+    /// A yaml string written in quotes, e.g. `name: 'app'`, told apart from other strings in
+    /// files read line by line or with `keep_string_quotes`
+    Quoted(String),
+    /// An `!env NAME:-default` tagged value, taken from the environment variable `NAME`, or the
+    /// variable named like the key as written in the yaml file, once all files are merged
+    EnvTag {
+        var_name: Option<String>,
+        default: Option<String>,
+    },
+}
+
+impl YamlScalar {
+    /// The value as it is written to the env map, an env tag being shown as written
+    pub(crate) fn text(&self) -> String {
+        match self {
+            YamlScalar::Plain(value) | YamlScalar::String(value) | YamlScalar::Quoted(value) => {
+                value.clone()
+            }
+            YamlScalar::EnvTag { var_name, default } => {
+                let mut text = String::from("!env");
+                if let Some(var_name) = var_name {
+                    text.push(' ');
+                    text.push_str(var_name);
+                }
+                if let Some(default) = default {
+                    text.push_str(":-");
+                    text.push_str(default);
+                }
+                text
+            }
+        }
+    }
+}
+
+/// Flattened values of a yaml file, in the order they were read
+pub(crate) type YamlValues = IndexMap<String, YamlScalar>;
+
+/// The values read from a yaml file, as cached between runs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct FileValues {
+    values: YamlValues,
+    /// Whether the file has the top-level `env_section` its values were taken from
+    has_env_section: bool,
+    /// The selected profiles the file has marked keys for
+    #[serde(default)]
+    used_profiles: HashSet<String>,
+    /// The line of the yaml file each key is set on, if known
+    #[serde(default)]
+    key_lines: HashMap<String, usize>,
+    /// The document the values were flattened from, kept for the yaml format only
+    #[serde(skip)]
+    tree: Option<Value>,
+}
+
+/// Values read from a single yaml file, or from the base env file
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub values: EnvMap,
+    /// Time spent reading and parsing the file, near zero if it came from the cache
+    pub parse_time: Duration,
+    scalars: YamlValues,
+    /// The line of the file each key is set on, if known
+    key_lines: HashMap<String, usize>,
+    tree: Option<Value>,
+}
+
+impl SourceFile {
+    /// Values read from an env file rather than a yaml file, e.g. the base env
+    pub fn new(path: PathBuf, values: EnvMap) -> Self {
+        let scalars = values
+            .iter()
+            .map(|(k, v)| (k.clone(), YamlScalar::Plain(v.clone())))
+            .collect();
+        let tree = values
+            .iter()
+            .map(|(k, v)| (Value::from(k.trim()), Value::from(v.trim())))
+            .collect();
+        Self {
+            path,
+            values,
+            parse_time: Duration::ZERO,
+            scalars,
+            key_lines: HashMap::new(),
+            tree: Some(Value::Mapping(tree)),
+        }
+    }
+
+    /// Values read from a yaml file
+    fn from_scalars(
+        path: PathBuf,
+        scalars: YamlValues,
+        key_lines: HashMap<String, usize>,
+        tree: Option<Value>,
+        parse_time: Duration,
+    ) -> Self {
+        Self {
+            path,
+            values: scalars
+                .iter()
+                .map(|(k, scalar)| (k.clone(), scalar.text()))
+                .collect(),
+            parse_time,
+            scalars,
+            key_lines,
+            tree,
+        }
+    }
+}
+
+/// Result of parsing the yaml files, before formatting
+#[derive(Debug)]
+pub struct Parsed {
+    /// The values read from each yaml file, in input order
+    pub source_files: Vec<SourceFile>,
+    /// The yaml files skipped because they could not be read or parsed
+    pub failed_paths: Vec<PathBuf>,
+    /// The merged and transformed variables
+    pub env_map: EnvMap,
+    /// The index in `source_files` of the file that set the merged value of each key
+    pub origins: HashMap<String, usize>,
+    /// The keys of the `changed_since` map that are gone
+    pub removed_keys: Vec<String>,
+    /// The keys whose value the env format writes in double quotes: those of yaml strings
+    /// holding a number, unless `unquote_numbers` is set, and those of yaml strings written in
+    /// quotes if `keep_string_quotes` is set
+    pub quoted_strings: HashSet<String>,
+    /// The layout of the merged yaml files, if the output format is yaml
+    pub yaml_layout: Option<YamlLayout>,
+    /// The warnings raised while parsing
+    pub warnings: Vec<Warning>,
+    /// How the `explain_key` got its final value, step by step
+    pub trace: Option<Vec<String>>,
+}
+
+/// Result of a conversion
+#[derive(Debug)]
+pub struct Conversion {
+    /// The values read from each yaml file, in input order
+    pub source_files: Vec<SourceFile>,
+    /// The yaml files skipped because they could not be read or parsed
+    pub failed_paths: Vec<PathBuf>,
+    /// The merged and transformed variables
+    pub env_map: EnvMap,
+    /// The index in `source_files` of the file that set the merged value of each key
+    pub origins: HashMap<String, usize>,
+    /// The warnings raised during the conversion
+    pub warnings: Vec<Warning>,
+    /// How the `explain_key` got its final value, step by step
+    pub trace: Option<Vec<String>>,
+    /// The env map formatted per the output options
+    pub output: String,
+}
+
+/// Get the URL of a yaml path starting with `http://` or `https://`
+fn as_url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Fetch a yaml file from a URL
+#[cfg(feature = "remote")]
+fn fetch_yaml_file(url: &str, timeout: Option<Duration>) -> Result<String, YamlToEnvError> {
+    crate::remote::fetch(url, timeout)
+}
+
+/// Fetch a yaml file from a URL, which needs the `remote` feature
+#[cfg(not(feature = "remote"))]
+fn fetch_yaml_file(url: &str, _timeout: Option<Duration>) -> Result<String, YamlToEnvError> {
+    Err(YamlToEnvError::RemoteDisabled {
+        url: String::from(url),
+    })
+}
+
+/// Read the content of a yaml file, following symlinks and fetching URLs
+fn read_yaml_file(path: &Path, timeout: Option<Duration>) -> Result<String, YamlToEnvError> {
+    if let Some(url) = as_url(path) {
+        return fetch_yaml_file(url, timeout);
+    }
+    std::fs::read_to_string(path).map_err(|_| match std::fs::read_link(path) {
+        Ok(target) if !path.exists() => YamlToEnvError::BrokenSymlink {
+            path: path.to_path_buf(),
+            target,
+        },
+        _ => YamlToEnvError::UnreadableFile {
+            path: path.to_path_buf(),
+        },
+    })
+}
+
+/// Write a yaml mapping key as part of an env key
+pub(crate) fn yaml_key_to_string(key: &Value) -> Option<String> {
+    match key {
+        Value::String(key) => Some(key.clone()),
+        Value::Number(key) => Some(key.to_string()),
+        Value::Bool(key) => Some(key.to_string()),
+        _ => None,
+    }
+}
+
+/// Read the environment variable name and default value of an `!env` tagged value, e.g.
+/// `!env`, `!env NAME`, `!env:-default` or `!env NAME:-default`
+fn parse_env_tag(tagged: &TaggedValue) -> Option<YamlScalar> {
+    let tag = tagged.tag.to_string();
+    let tag_suffix = tag.strip_prefix("!env")?;
+    let tag_args = match &tagged.value {
+        Value::Null => String::from(tag_suffix),
+        Value::String(args) => format!("{} {}", tag_suffix, args),
+        _ => return None,
+    };
+    if !tag_args.is_empty() && !tag_args.starts_with(|c: char| c == ':' || c.is_whitespace()) {
+        return None;
+    }
+    let (var_name, default) = match tag_args.split_once(":-") {
+        Some((var_name, default)) => (var_name, Some(String::from(default))),
+        None => (tag_args.as_str(), None),
+    };
+    let var_name = Some(var_name.trim())
+        .filter(|var_name| !var_name.is_empty())
+        .map(String::from);
+    Some(YamlScalar::EnvTag { var_name, default })
+}
+
+/// The content of a `!dotenv` tagged value, or `Some(None)` if it is not a string
+fn as_dotenv_block(value: &Value) -> Option<Option<&str>> {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == "!dotenv" => Some(tagged.value.as_str()),
+        _ => None,
+    }
+}
+
+/// A copy of a yaml value without the `QUOTED_TAG` tags, e.g. to write it as JSON
+fn untag_quoted_values(value: &Value) -> Value {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == QUOTED_TAG => tagged.value.clone(),
+        Value::Tagged(tagged) => Value::Tagged(Box::new(TaggedValue {
+            tag: tagged.tag.clone(),
+            value: untag_quoted_values(&tagged.value),
+        })),
+        Value::Sequence(sequence) => {
+            Value::Sequence(sequence.iter().map(untag_quoted_values).collect())
+        }
+        Value::Mapping(mapping) => Value::Mapping(
+            mapping
+                .iter()
+                .map(|(k, v)| (k.clone(), untag_quoted_values(v)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Flatten a yaml value into env variables per `settings`, joining the keys of nested mappings
+/// with the separator and the indices of sequences with the array separator. `key` is the
+/// flattened key of the value, made of `depth` nested keys. Fails on an unsupported structure,
+/// e.g. a mapping key that is not a scalar, and on keys nested deeper than the maximum depth.
+fn flatten_yaml_value(
+    key: Option<&str>,
+    value: &Value,
+    depth: usize,
+    settings: &ParseSettings,
+    path: &Path,
+    env_map: &mut YamlValues,
+) -> Result<(), YamlToEnvError> {
+    let unsupported_structure = || YamlToEnvError::UnsupportedStructure {
+        path: path.to_path_buf(),
+    };
+    let join_key = |separator: &str, sub_key: &str| {
+        let sub_key = match key {
+            Some(key) => format!("{}{}{}", key, separator, sub_key),
+            None => String::from(sub_key),
+        };
+        if depth >= settings.max_depth {
+            return Err(YamlToEnvError::MaxDepthExceeded {
+                path: path.to_path_buf(),
+                key: sub_key,
+                max_depth: settings.max_depth,
+            });
+        }
+        Ok(sub_key)
+    };
+    let scalar = match value {
+        Value::Mapping(mapping) => {
+            for (sub_key, sub_value) in mapping.iter() {
+                let sub_key = join_key(
+                    &settings.separator,
+                    &yaml_key_to_string(sub_key).ok_or_else(unsupported_structure)?,
+                )?;
+                // the variables of a `!dotenv` block are added to the enclosing mapping
+                if let Some(content) = as_dotenv_block(sub_value) {
+                    let embedded = parse_env_str(content.ok_or_else(unsupported_structure)?)
+                        .map_err(|err| YamlToEnvError::InvalidEmbeddedDotenv {
+                            path: path.to_path_buf(),
+                            key: sub_key.clone(),
+                            line: match err {
+                                YamlToEnvError::InvalidEnvLine { line, .. } => line,
+                                err => err.to_string(),
+                            },
+                        })?;
+                    for (k, v) in embedded.into_iter() {
+                        env_map.insert(join_key(&settings.separator, &k)?, YamlScalar::Plain(v));
+                    }
+                    continue;
+                }
+                flatten_yaml_value(
+                    Some(&sub_key),
+                    sub_value,
+                    depth + 1,
+                    settings,
+                    path,
+                    env_map,
+                )?;
+            }
+            return Ok(());
+        }
+        // a sequence holding a mapping with non-string keys has no json form
+        Value::Sequence(_) if settings.array_mode == ArrayMode::Json => YamlScalar::Plain(
+            serde_json::to_string(&untag_quoted_values(value))
+                .map_err(|_| unsupported_structure())?,
+        ),
+        Value::Sequence(sequence)
+            if settings.array_mode == ArrayMode::Join
+                && sequence
+                    .iter()
+                    .all(|item| item.as_mapping().is_none() && item.as_sequence().is_none()) =>
+        {
+            let items = sequence
+                .iter()
+                .map(|item| match item {
+                    Value::Bool(item) => item.to_string(),
+                    Value::Number(item) => item.to_string(),
+                    Value::String(item) => item.clone(),
+                    Value::Tagged(item) if item.tag == QUOTED_TAG => {
+                        String::from(item.value.as_str().unwrap_or_default())
+                    }
+                    _ => String::new(),
+                })
+                .collect::<Vec<String>>();
+            YamlScalar::Plain(items.join(","))
+        }
+        Value::Sequence(sequence) => {
+            for (i, item) in sequence.iter().enumerate() {
+                let sub_key = join_key(&settings.array_separator, &i.to_string())?;
+                flatten_yaml_value(Some(&sub_key), item, depth + 1, settings, path, env_map)?;
+            }
+            return Ok(());
+        }
+        // a `!json` tagged value is written as compact JSON whatever its structure
+        Value::Tagged(tagged) if tagged.tag == "!json" => YamlScalar::Plain(
+            serde_json::to_string(&untag_quoted_values(&tagged.value))
+                .map_err(|_| unsupported_structure())?,
+        ),
+        Value::Tagged(tagged) if tagged.tag == QUOTED_TAG => {
+            YamlScalar::Quoted(String::from(tagged.value.as_str().unwrap_or_default()))
+        }
+        Value::Tagged(tagged) => match parse_env_tag(tagged) {
+            Some(env_tag) => env_tag,
+            None => return flatten_yaml_value(key, &tagged.value, depth, settings, path, env_map),
+        },
+        // empty values are left out
+        Value::Null => return Ok(()),
+        Value::Bool(value) => YamlScalar::Plain(value.to_string()),
+        Value::Number(value) => YamlScalar::Plain(value.to_string()),
+        Value::String(value) => YamlScalar::String(value.clone()),
+    };
+    // a scalar document has no key to assign the value to
+    env_map.insert(String::from(key.ok_or_else(unsupported_structure)?), scalar);
+    Ok(())
+}
+
+/// The `default`, `common` and `env_section` top-level mappings of a yaml document, in the
+/// order their values override each other, along with whether it has the `env_section` one
+fn select_env_section<'a>(document: &'a Value, env_section: &str) -> (Vec<&'a Value>, bool) {
+    let section = |name: &str| match document {
+        Value::Mapping(mapping) => mapping
+            .iter()
+            .find(|(k, _)| yaml_key_to_string(k).is_some_and(|k| k.trim() == name))
+            .map(|(_, v)| v)
+            .filter(|v| v.is_mapping()),
+        _ => None,
+    };
+    let env_values = section(env_section);
+    let sections = ["default", "common"]
+        .into_iter()
+        .filter_map(section)
+        .chain(env_values)
+        .collect();
+    (sections, env_values.is_some())
+}
+
+/// Merge a yaml document into another, recursing into the mappings both have at a key and
+/// replacing everything else
+fn merge_yaml_trees(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (k, v) in overlay {
+                match base.get_mut(&k) {
+                    Some(base_value) => merge_yaml_trees(base_value, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Sort the keys of every mapping of a yaml document
+fn sort_yaml_tree(value: &mut Value) {
+    match value {
+        Value::Mapping(mapping) => {
+            let mut entries = std::mem::take(mapping).into_iter().collect::<Vec<_>>();
+            entries.sort_by_key(|(k, _)| yaml_key_to_string(k));
+            for (k, mut v) in entries {
+                sort_yaml_tree(&mut v);
+                mapping.insert(k, v);
+            }
+        }
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(sort_yaml_tree),
+        Value::Tagged(tagged) => sort_yaml_tree(&mut tagged.value),
+        _ => {}
+    }
+}
+
+/// Set the scalars of a yaml document to the values of the flattened keys they were read into,
+/// taken from `remaining`, dropping those whose key is not there, as `flatten_yaml_value`
+/// flattens them per `settings`. Returns whether the value is kept.
+fn fill_yaml_layout(
+    value: &mut Value,
+    key: Option<&str>,
+    settings: &ParseSettings,
+    remaining: &mut HashMap<String, String>,
+) -> bool {
+    let join_key = |separator: &str, sub_key: &str| match key {
+        Some(key) => format!("{}{}{}", key, separator, sub_key),
+        None => String::from(sub_key),
+    };
+    let is_one_variable = |sequence: &[Value]| match settings.array_mode {
+        ArrayMode::Index => false,
+        ArrayMode::Join => sequence
+            .iter()
+            .all(|item| item.as_mapping().is_none() && item.as_sequence().is_none()),
+        ArrayMode::Json => true,
+    };
+    match value {
+        Value::Mapping(mapping) => {
+            mapping.retain(|sub_key, sub_value| match yaml_key_to_string(sub_key) {
+                Some(sub_key) => {
+                    let sub_key = join_key(&settings.separator, &sub_key);
+                    fill_yaml_layout(sub_value, Some(&sub_key), settings, remaining)
+                }
+                None => false,
+            });
+            !mapping.is_empty()
+        }
+        Value::Sequence(sequence) if !is_one_variable(sequence) => {
+            let mut i = 0;
+            sequence.retain_mut(|item| {
+                let sub_key = join_key(&settings.array_separator, &i.to_string());
+                i += 1;
+                fill_yaml_layout(item, Some(&sub_key), settings, remaining)
+            });
+            !sequence.is_empty()
+        }
+        Value::Tagged(tagged) if tagged.tag == QUOTED_TAG => {
+            *value = std::mem::take(&mut tagged.value);
+            fill_yaml_layout(value, key, settings, remaining)
+        }
+        Value::Tagged(tagged)
+            if tagged.tag != "!json"
+                && tagged.tag != "!dotenv"
+                && parse_env_tag(tagged).is_none() =>
+        {
+            fill_yaml_layout(&mut tagged.value, key, settings, remaining)
+        }
+        _ => {
+            let Some(final_value) = key.and_then(|key| remaining.remove(key.trim())) else {
+                return false;
+            };
+            let unchanged = match value {
+                Value::Bool(scalar) => scalar.to_string() == final_value,
+                Value::Number(scalar) => scalar.to_string() == final_value,
+                Value::String(scalar) => *scalar == final_value,
+                _ => false,
+            };
+            if !unchanged {
+                *value = Value::String(final_value);
+            }
+            true
+        }
+    }
+}
+
+/// The yaml documents the variables were flattened from, merged in the order of the files,
+/// which the yaml format nests the variables by
+#[derive(Clone, Debug)]
+pub struct YamlLayout {
+    tree: Value,
+    settings: ParseSettings,
+}
+
+impl YamlLayout {
+    /// Format an env map as a YAML mapping sorted by key, writing each variable where the
+    /// yaml files set it. Variables the files do not set as written, e.g. renamed keys or
+    /// defaulted allowlisted keys, are top-level keys.
+    pub fn to_yaml(&self, env_map: &EnvMap) -> String {
+        let mut remaining = env_map
+            .sorted_pairs()
+            .into_iter()
+            .map(|(k, v)| (String::from(k), String::from(v)))
+            .collect::<HashMap<String, String>>();
+        let mut tree = self.tree.clone();
+        fill_yaml_layout(&mut tree, None, &self.settings, &mut remaining);
+        if let Value::Mapping(mapping) = &mut tree {
+            for (k, v) in remaining {
+                mapping.insert(Value::from(k), Value::from(v));
+            }
+        }
+        sort_yaml_tree(&mut tree);
+        // a yaml document always serializes
+        serde_yaml::to_string(&tree).unwrap()
+    }
+}
+
+/// Read a single yaml file into an env map per `settings`, keeping the document it was
+/// flattened from if `keep_tree` is set
+fn parse_yaml_file(
+    path: &Path,
+    settings: &ParseSettings,
+    keep_tree: bool,
+    timeout: Option<Duration>,
+) -> Result<FileValues, YamlToEnvError> {
+    let mut file = read_yaml_file(path, timeout)?;
+    if settings.yaml_1_1_bools {
+        file = normalize_yaml_1_1_bools(&file);
+    }
+    if settings.keep_string_quotes {
+        file = tag_quoted_values(&file);
+    }
+    let mut document =
+        serde_yaml::from_str::<Value>(&file).map_err(|err| YamlToEnvError::InvalidYaml {
+            path: path.to_path_buf(),
+            reason: err.to_string(),
+        })?;
+    // resolve `<<: *anchor` merge keys
+    document
+        .apply_merge()
+        .map_err(|_| YamlToEnvError::UnsupportedStructure {
+            path: path.to_path_buf(),
+        })?;
+
+    let (sections, has_env_section) = match &settings.env_section {
+        Some(env_section) => select_env_section(&document, env_section),
+        None => (vec![&document], false),
+    };
+    let mut values = YamlValues::new();
+    let mut used_profiles = HashSet::new();
+    let mut tree = keep_tree.then(|| Value::Mapping(Default::default()));
+    for section in sections {
+        let section =
+            select_profile_sections(section.clone(), &settings.profiles, &mut used_profiles);
+        flatten_yaml_value(None, &section, 0, settings, path, &mut values)?;
+        if let Some(tree) = tree.as_mut() {
+            merge_yaml_trees(tree, section);
+        }
+    }
+    let marks = read_document_marks(&file);
+    let env_section = settings.env_section.as_deref();
+    if settings.strip_trailing_value_newline {
+        let clipped_values =
+            flatten_key_paths(marks.clipped_values, env_section, settings, &values);
+        for (key, _) in clipped_values.into_iter().filter(|(_, clipped)| *clipped) {
+            if let Some(YamlScalar::String(value)) = values.get_mut(&key) {
+                if value.ends_with('\n') {
+                    value.pop();
+                }
+            }
+        }
+    }
+    let key_lines = flatten_key_paths(marks.key_lines, env_section, settings, &values);
+    Ok(FileValues {
+        values,
+        has_env_section,
+        used_profiles,
+        key_lines,
+        tree,
+    })
+}
+
+/// Read a yaml file line by line, as done before yaml parsing: `#` comments and blank lines
+/// are skipped, every other line must be a `key: value` pair split at its first colon, and
+/// pairs with an empty value are left out. Values in matching quotes are strings. Returns
+/// the values along with the line each key is set on.
+fn parse_yaml_lines(
+    path: &Path,
+    timeout: Option<Duration>,
+) -> Result<(YamlValues, KeyPaths<usize>), YamlToEnvError> {
+    let file = read_yaml_file(path, timeout)?;
+    let mut values = YamlValues::new();
+    let mut key_lines = Vec::new();
+    for (i, line) in file.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) =
+            line.split_once(':')
+                .ok_or_else(|| YamlToEnvError::UnsupportedStructure {
+                    path: path.to_path_buf(),
+                })?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            continue;
+        }
+        let quoted = ['"', '\'']
+            .into_iter()
+            .find_map(|quote| value.strip_prefix(quote)?.strip_suffix(quote));
+        let scalar = match quoted {
+            Some(quoted) => YamlScalar::Quoted(String::from(quoted)),
+            None => YamlScalar::Plain(String::from(value)),
+        };
+        values.insert(String::from(key), scalar);
+        key_lines.push((vec![KeyPart::Key(String::from(key))], i + 1));
+    }
+    Ok((values, key_lines))
+}
+
+/// Read a single yaml file per `options`, without the cache, into its flattened values and
+/// the document they were flattened from
+pub(crate) fn read_yaml_document(
+    path: &Path,
+    options: &ConvertOptions,
+) -> Result<(YamlValues, Value), YamlToEnvError> {
+    let file_values = parse_yaml_file(path, &options.parse_settings(), true, options.timeout)?;
+    let tree = file_values.tree.unwrap_or_default();
+    Ok((file_values.values, tree))
+}
+
+/// Derive an env key prefix from the file name, e.g. `DATABASE_` for `database.yaml` and
+/// `MY_APP_` for `my-app.yaml`
+fn prefix_from_filename(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let mut prefix = stem
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect::<String>();
+    if prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        prefix.insert(0, '_');
+    }
+    prefix.push('_');
+    prefix
+}
+
+/// Split a `@profile` suffix off a yaml key, e.g. `DEBUG@dev`, if what follows the last `@`
+/// can name a profile, i.e. is made of letters, digits, `-` and `_`
+fn split_profile_suffix(key: &str) -> Option<(&str, &str)> {
+    let (key, profile) = key.trim().rsplit_once('@')?;
+    let is_profile_name = !profile.is_empty()
+        && profile
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    (!key.is_empty() && is_profile_name).then_some((key, profile))
+}
+
+/// Drop the mapping keys marked for other profiles with a `@profile` suffix, e.g.
+/// `DEBUG@dev`, and merge the values of the keys of the selected profiles over the unmarked
+/// key, in the order of `profiles`, so that `DATABASE@dev: {host: x}` overrides the host of
+/// `DATABASE` only. The selected profiles seen are added to `used_profiles`. Keys are left as
+/// they are if no profile is selected.
+fn select_profile_sections(
+    value: Value,
+    profiles: &[String],
+    used_profiles: &mut HashSet<String>,
+) -> Value {
+    if profiles.is_empty() {
+        return value;
+    }
+    match value {
+        Value::Mapping(mapping) => {
+            let mut selected = serde_yaml::Mapping::new();
+            let mut marked = Vec::new();
+            for (k, v) in mapping {
+                let suffix = k.as_str().and_then(split_profile_suffix);
+                match suffix {
+                    Some((key, key_profile)) => {
+                        marked.push((String::from(key), String::from(key_profile), v))
+                    }
+                    None => {
+                        selected.insert(k, v);
+                    }
+                }
+            }
+            for profile in profiles.iter() {
+                for (key, _, v) in marked.iter().filter(|(_, p, _)| p == profile) {
+                    used_profiles.insert(profile.clone());
+                    let key = Value::from(key.as_str());
+                    match selected.get_mut(&key) {
+                        Some(base) => merge_yaml_trees(base, v.clone()),
+                        None => {
+                            selected.insert(key, v.clone());
+                        }
+                    }
+                }
+            }
+            Value::Mapping(
+                selected
+                    .into_iter()
+                    .map(|(k, v)| (k, select_profile_sections(v, profiles, used_profiles)))
+                    .collect(),
+            )
+        }
+        Value::Sequence(sequence) => Value::Sequence(
+            sequence
+                .into_iter()
+                .map(|item| select_profile_sections(item, profiles, used_profiles))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Select the `@profile` marked keys of flattened values read without a yaml document, as
+/// `select_profile_sections` does for the keys of a mapping
+fn select_profile_keys(
+    values: YamlValues,
+    profiles: &[String],
+    used_profiles: &mut HashSet<String>,
+) -> YamlValues {
+    if profiles.is_empty() {
+        return values;
+    }
+    let (marked, mut selected): (Vec<_>, Vec<_>) = values
+        .into_iter()
+        .map(|(k, v)| match split_profile_suffix(&k) {
+            Some((key, key_profile)) => (Some(String::from(key_profile)), (String::from(key), v)),
+            None => (None, (k, v)),
+        })
+        .partition(|(key_profile, _)| key_profile.is_some());
+    for profile in profiles.iter() {
+        let profile_keys = marked
+            .iter()
+            .filter(|(key_profile, _)| key_profile.as_ref() == Some(profile))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !profile_keys.is_empty() {
+            used_profiles.insert(profile.clone());
+        }
+        selected.extend(profile_keys);
+    }
+    selected.into_iter().map(|(_, pair)| pair).collect()
+}
+
+/// The boolean a plain scalar stands for in yaml 1.1, if any
+fn yaml_1_1_bool(scalar: &str) -> Option<&'static str> {
+    match scalar {
+        "y" | "Y" | "yes" | "Yes" | "YES" | "true" | "True" | "TRUE" | "on" | "On" | "ON" => {
+            Some("true")
+        }
+        "n" | "N" | "no" | "No" | "NO" | "false" | "False" | "FALSE" | "off" | "Off" | "OFF" => {
+            Some("false")
+        }
+        _ => None,
+    }
+}
+
+/// Collects the plain, untagged scalars of a yaml document that are booleans in yaml 1.1
+struct PlainBools {
+    /// The char offset and char length of each scalar, with the boolean it stands for
+    spans: Vec<(usize, usize, &'static str)>,
+}
+
+impl MarkedEventReceiver for PlainBools {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        if let Event::Scalar(scalar, TScalarStyle::Plain, _, None) = event {
+            if let Some(boolean) = yaml_1_1_bool(&scalar) {
+                self.spans
+                    .push((mark.index(), scalar.chars().count(), boolean));
+            }
+        }
+    }
+}
+
+/// Rewrite the plain keys and values that are booleans in yaml 1.1, e.g. `no` or `on`, as
+/// `false` or `true`. Quoted and tagged scalars like `country: 'no'` are left as they are.
+/// Content that does not parse is returned unchanged for serde_yaml to report the error.
+fn normalize_yaml_1_1_bools(content: &str) -> String {
+    let mut bools = PlainBools { spans: Vec::new() };
+    if Parser::new_from_str(content)
+        .load(&mut bools, true)
+        .is_err()
+    {
+        return String::from(content);
+    }
+    // byte offset of each char, and of the end of the content
+    let offsets = content
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([content.len()])
+        .collect::<Vec<usize>>();
+    let mut normalized = String::with_capacity(content.len());
+    let mut copied = 0;
+    for (index, length, boolean) in bools.spans {
+        let (start, end) = match (offsets.get(index), offsets.get(index + length)) {
+            (Some(start), Some(end)) if *start >= copied => (*start, *end),
+            _ => continue,
+        };
+        if yaml_1_1_bool(&content[start..end]) != Some(boolean) {
+            continue;
+        }
+        normalized.push_str(&content[copied..start]);
+        normalized.push_str(boolean);
+        copied = end;
+    }
+    normalized.push_str(&content[copied..]);
+    normalized
+}
+
+/// The tag `tag_quoted_values` marks the yaml strings written in quotes with
+const QUOTED_TAG: &str = "!quoted";
+
+/// Collects the quoted, untagged scalars of a yaml document that are not mapping keys
+struct QuotedValues {
+    /// The collections enclosing the next node, `None` for a sequence and whether the node is
+    /// a key for a mapping
+    collections: Vec<Option<bool>>,
+    /// The char offset of the opening quote of each scalar
+    offsets: HashSet<usize>,
+}
+
+impl QuotedValues {
+    /// Note the end of a node, the next node of a mapping being a value after a key and a key
+    /// after a value
+    fn end_node(&mut self) {
+        if let Some(Some(is_key)) = self.collections.last_mut() {
+            *is_key = !*is_key;
+        }
+    }
+}
+
+impl MarkedEventReceiver for QuotedValues {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(_, style, _, tag) => {
+                let is_key = self.collections.last() == Some(&Some(true));
+                let is_quoted = matches!(
+                    style,
+                    TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted
+                );
+                if is_quoted && tag.is_none() && !is_key {
+                    self.offsets.insert(mark.index());
+                }
+                self.end_node();
+            }
+            Event::Alias(_) => self.end_node(),
+            Event::SequenceStart(..) => self.collections.push(None),
+            Event::MappingStart(..) => self.collections.push(Some(true)),
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.collections.pop();
+                self.end_node();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Tag the values written in quotes, e.g. `name: 'app'`, with `QUOTED_TAG`, so they are told
+/// apart from other strings once parsed. Keys and tagged values are left as they are. Content
+/// that does not parse is returned unchanged for serde_yaml to report the error.
+fn tag_quoted_values(content: &str) -> String {
+    let mut quoted = QuotedValues {
+        collections: Vec::new(),
+        offsets: HashSet::new(),
+    };
+    if Parser::new_from_str(content)
+        .load(&mut quoted, true)
+        .is_err()
+    {
+        return String::from(content);
+    }
+    let mut tagged = String::with_capacity(content.len() + quoted.offsets.len() * 8);
+    for (i, c) in content.chars().enumerate() {
+        if quoted.offsets.contains(&i) && matches!(c, '"' | '\'') {
+            tagged.push_str(QUOTED_TAG);
+            tagged.push(' ');
+        }
+        tagged.push(c);
+    }
+    tagged
+}
+
+/// A part of the path to a node of a yaml document
+#[derive(Clone, Debug)]
+enum KeyPart {
+    Key(String),
+    Index(usize),
+}
+
+/// A value for each path to a node of a yaml document
+type KeyPaths<T> = Vec<(Vec<KeyPart>, T)>;
+
+/// A collection enclosing a node of a yaml document, as read by `DocumentMarks`
+enum KeyFrame {
+    /// A sequence, with the index of its next item
+    Sequence(usize),
+    /// A mapping whose next node is a key
+    MappingKey,
+    /// A mapping whose next node is the value of a key, `None` if it is not a scalar
+    MappingValue(Option<String>),
+}
+
+/// Collects where the nodes of a yaml document start and how its scalars are written, along
+/// with their paths
+struct DocumentMarks<'a> {
+    content_lines: Vec<&'a str>,
+    frames: Vec<KeyFrame>,
+    /// The line each mapping key and sequence item starts on
+    key_lines: KeyPaths<usize>,
+    /// Whether each scalar value is a `|` or `>` block scalar keeping its last line break, i.e.
+    /// one without a `+` or `-` chomping indicator
+    clipped_values: KeyPaths<bool>,
+}
+
+impl DocumentMarks<'_> {
+    /// The path to the next node of the innermost collection, `None` inside a mapping key that
+    /// is not a scalar
+    fn path(&self, frames: &[KeyFrame]) -> Option<Vec<KeyPart>> {
+        frames
+            .iter()
+            .map(|frame| match frame {
+                KeyFrame::Sequence(i) => Some(KeyPart::Index(*i)),
+                KeyFrame::MappingValue(Some(key)) => Some(KeyPart::Key(key.clone())),
+                KeyFrame::MappingKey | KeyFrame::MappingValue(None) => None,
+            })
+            .collect()
+    }
+
+    /// Whether a scalar starting at `mark` is a block scalar keeping its last line break. Its
+    /// header ends the last line before its content that is not blank.
+    fn is_clipped_block_scalar(&self, style: TScalarStyle, mark: Marker) -> bool {
+        if !matches!(style, TScalarStyle::Literal | TScalarStyle::Folded) {
+            return false;
+        }
+        let header = self.content_lines[..mark.line().saturating_sub(1)]
+            .iter()
+            .rev()
+            .find(|line| !line.trim().is_empty());
+        let indicator = header.and_then(|header| {
+            header
+                .split_whitespace()
+                .take_while(|word| !word.starts_with('#'))
+                .last()
+        });
+        indicator.is_some_and(|indicator| {
+            indicator.starts_with(['|', '>']) && !indicator.contains(['+', '-'])
+        })
+    }
+
+    /// Note the start of a node, recording its line if it is a sequence item
+    fn start_node(&mut self, mark: Marker) {
+        if matches!(self.frames.last(), Some(KeyFrame::Sequence(_))) {
+            if let Some(path) = self.path(&self.frames) {
+                self.key_lines.push((path, mark.line()));
+            }
+        }
+    }
+
+    /// Note the end of a node, the next node of a mapping being a value after a key and a key
+    /// after a value
+    fn end_node(&mut self) {
+        match self.frames.last_mut() {
+            Some(KeyFrame::Sequence(i)) => *i += 1,
+            Some(frame @ KeyFrame::MappingKey) => *frame = KeyFrame::MappingValue(None),
+            Some(frame @ KeyFrame::MappingValue(_)) => *frame = KeyFrame::MappingKey,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for DocumentMarks<'_> {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(key, ..) if matches!(self.frames.last(), Some(KeyFrame::MappingKey)) => {
+                let enclosing = &self.frames[..self.frames.len() - 1];
+                if let Some(mut path) = self.path(enclosing) {
+                    path.push(KeyPart::Key(key.clone()));
+                    self.key_lines.push((path, mark.line()));
+                }
+                if let Some(frame) = self.frames.last_mut() {
+                    *frame = KeyFrame::MappingValue(Some(key));
+                }
+            }
+            Event::Scalar(_, style, ..) => {
+                self.start_node(mark);
+                if let Some(path) = self.path(&self.frames) {
+                    let clipped = self.is_clipped_block_scalar(style, mark);
+                    self.clipped_values.push((path, clipped));
+                }
+                self.end_node();
+            }
+            Event::Alias(_) => {
+                self.start_node(mark);
+                self.end_node();
+            }
+            Event::SequenceStart(..) => {
+                self.start_node(mark);
+                self.frames.push(KeyFrame::Sequence(0));
+            }
+            Event::MappingStart(..) => {
+                self.start_node(mark);
+                self.frames.push(KeyFrame::MappingKey);
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.frames.pop();
+                self.end_node();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Read where the nodes of a yaml document start and how its scalars are written. A document
+/// that does not parse has no marks.
+fn read_document_marks(content: &str) -> DocumentMarks<'_> {
+    let mut marks = DocumentMarks {
+        content_lines: content.lines().collect(),
+        frames: Vec::new(),
+        key_lines: Vec::new(),
+        clipped_values: Vec::new(),
+    };
+    if Parser::new_from_str(content)
+        .load(&mut marks, true)
+        .is_err()
+    {
+        marks.key_lines.clear();
+        marks.clipped_values.clear();
+    }
+    marks
+}
+
+/// The value for each flattened key of `values` from those of the yaml nodes it was read
+/// from, dropping the top-level section per `env_section` and the `@profile` suffixes per
+/// `settings`. A key set by several sections or profiles has the value of the one whose value
+/// is kept.
+fn flatten_key_paths<T>(
+    key_paths: KeyPaths<T>,
+    env_section: Option<&str>,
+    settings: &ParseSettings,
+    values: &YamlValues,
+) -> HashMap<String, T> {
+    // the keys with the rank of the section and the profile setting them
+    let mut ranked_keys = Vec::new();
+    'keys: for (mut parts, value) in key_paths {
+        let mut section_rank = 0;
+        if let Some(env_section) = env_section {
+            let sections = ["default", "common", env_section];
+            let section = match parts.first() {
+                Some(KeyPart::Key(section)) => sections.iter().position(|s| *s == section.trim()),
+                _ => None,
+            };
+            match section {
+                Some(rank) if parts.len() > 1 => section_rank = rank,
+                _ => continue,
+            }
+            parts.remove(0);
+        }
+        let mut profile_rank = 0;
+        let mut key = String::new();
+        for (i, part) in parts.iter().enumerate() {
+            match part {
+                KeyPart::Key(part) => {
+                    let part = match split_profile_suffix(part) {
+                        Some((part, profile)) if !settings.profiles.is_empty() => {
+                            match settings.profiles.iter().position(|p| p == profile) {
+                                Some(rank) => profile_rank = profile_rank.max(rank + 1),
+                                None => continue 'keys,
+                            }
+                            part
+                        }
+                        _ => part,
+                    };
+                    if i > 0 {
+                        key.push_str(&settings.separator);
+                    }
+                    key.push_str(part);
+                }
+                KeyPart::Index(index) => {
+                    key.push_str(&settings.array_separator);
+                    key.push_str(&index.to_string());
+                }
+            }
+        }
+        if values.contains_key(&key) {
+            ranked_keys.push(((section_rank, profile_rank), key, value));
+        }
+    }
+    ranked_keys.sort_by_key(|(rank, _, _)| *rank);
+    ranked_keys
+        .into_iter()
+        .map(|(_, key, value)| (key, value))
+        .collect()
+}
+
+/// Read yaml files, reusing cached results for unchanged files.
+/// With `OnParseError::Continue`, files that fail to parse are skipped with a warning
+/// and added to `failed_paths`. Renames of the traced key are noted in `trace`.
+fn read_yaml_files(
+    paths: &[PathBuf],
+    mut cache: Option<&mut ParseCache>,
+    options: &ConvertOptions,
+    failed_paths: &mut Vec<PathBuf>,
+    warnings: &mut Vec<Warning>,
+    mut trace: Option<&mut KeyTrace>,
+) -> Result<Vec<SourceFile>, YamlToEnvError> {
+    let settings = options.parse_settings();
+    // the cache does not keep the documents the yaml format writes
+    let keep_tree = options.format.output_format == OutputFormat::Yaml;
+    let mut source_files = Vec::new();
+    let mut used_profiles = HashSet::new();
+    let mut has_env_section = false;
+
+    for path in paths.iter() {
+        let started_at = Instant::now();
+        let cached = cache
+            .as_deref()
+            .filter(|_| !keep_tree)
+            .and_then(|cache| cache.get(path))
+            .cloned();
+        let file_values = match cached {
+            Some(file_values) => file_values,
+            None => match (
+                parse_yaml_file(path, &settings, keep_tree, options.timeout),
+                options.on_parse_error,
+            ) {
+                (Ok(file_values), _) => {
+                    if let Some(cache) = cache.as_deref_mut() {
+                        cache.insert(path, file_values.clone());
+                    }
+                    file_values
+                }
+                // the fallback values are not cached, so the warning is raised on every run
+                (Err(YamlToEnvError::InvalidYaml { reason, .. }), _) if options.lenient_yaml => {
+                    let (values, key_lines) = match parse_yaml_lines(path, options.timeout) {
+                        Ok(read) => read,
+                        Err(err) if options.on_parse_error == OnParseError::Continue => {
+                            warnings.push(Warning::SkippedFile {
+                                path: path.clone(),
+                                reason: err.to_string(),
+                            });
+                            failed_paths.push(path.clone());
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    warnings.push(Warning::LenientYaml {
+                        path: path.clone(),
+                        reason,
+                    });
+                    let mut used_profiles = HashSet::new();
+                    let values = select_profile_keys(values, &options.profiles, &mut used_profiles);
+                    let key_lines = flatten_key_paths(key_lines, None, &settings, &values);
+                    FileValues {
+                        values,
+                        has_env_section: false,
+                        used_profiles,
+                        key_lines,
+                        tree: None,
+                    }
+                }
+                (Err(err), OnParseError::Continue) => {
+                    warnings.push(Warning::SkippedFile {
+                        path: path.clone(),
+                        reason: err.to_string(),
+                    });
+                    failed_paths.push(path.clone());
+                    continue;
+                }
+                (Err(err), OnParseError::Abort) => return Err(err),
+            },
+        };
+        has_env_section |= file_values.has_env_section;
+        used_profiles.extend(file_values.used_profiles);
+        let values = file_values.values;
+        let prefix = options.file_prefixes.get(path).cloned().or_else(|| {
+            options
+                .prefix_from_filename
+                .then(|| prefix_from_filename(path))
+        });
+        let (values, key_lines) = if prefix.is_some() || options.key_case != KeyCase::Preserve {
+            let prefix = prefix.unwrap_or_default();
+            let mut key_lines = HashMap::new();
+            let values = values
+                .into_iter()
+                .map(|(k, v)| {
+                    let cased_key = options.key_case.apply(k.trim());
+                    let renamed_key = format!("{}{}", prefix, cased_key);
+                    if let Some(trace) = trace.as_deref_mut() {
+                        trace.record_rename(path, &k, &cased_key, &renamed_key);
+                    }
+                    if let Some(line) = file_values.key_lines.get(&k) {
+                        key_lines.insert(renamed_key.clone(), *line);
+                    }
+                    // an `!env` tag without a name reads the variable named like the key as
+                    // written, not as renamed
+                    let v = match v {
+                        YamlScalar::EnvTag {
+                            var_name: None,
+                            default,
+                        } => YamlScalar::EnvTag {
+                            var_name: Some(String::from(k.trim())),
+                            default,
+                        },
+                        v => v,
+                    };
+                    (renamed_key, v)
+                })
+                .collect();
+            (values, key_lines)
+        } else {
+            (values, file_values.key_lines)
+        };
+        source_files.push(SourceFile::from_scalars(
+            path.clone(),
+            values,
+            key_lines,
+            file_values.tree,
+            started_at.elapsed(),
+        ));
+    }
+
+    if let Some(env_section) = options.env_section.as_deref().filter(|_| !has_env_section) {
+        return Err(YamlToEnvError::MissingEnvSection {
+            env_section: String::from(env_section),
+        });
+    }
+    if let Some(unused_profile) = options
+        .profiles
+        .iter()
+        .find(|profile| !used_profiles.contains(*profile))
+    {
+        return Err(YamlToEnvError::UnusedProfile {
+            profile: unused_profile.clone(),
+        });
+    }
+
+    Ok(source_files)
+}
+
+/// Describe where a key is set, e.g. `config/app.yaml:3`, leaving out the line if unknown
+fn describe_key_location(source_file: &SourceFile, key: &str) -> String {
+    match source_file.key_lines.get(key) {
+        Some(line) => format!("{}:{}", source_file.path.display(), line),
+        None => source_file.path.display().to_string(),
+    }
+}
+
+/// Add values of all yaml files to env map, handling keys set by several files per
+/// `on_conflict`. The base env, if any, is the first source file and always overridden.
+/// With `case_insensitive_keys`, keys differing only in case are the same variable and the
+/// casing written last is kept. Returns the map along with the index of the source file
+/// that set each key. The values of the traced key kept or dropped are noted in `trace`.
+fn create_env_hashmap(
+    source_files: &[SourceFile],
+    options: &ConvertOptions,
+    warnings: &mut Vec<Warning>,
+    mut trace: Option<&mut KeyTrace>,
+) -> Result<(EnvMap, HashMap<String, usize>), YamlToEnvError> {
+    let mut env_hash_map = EnvMap::new();
+    // index of the source file that set each key
+    let mut origins: HashMap<String, usize> = HashMap::new();
+    // the key of the map for each key in lower case, with `case_insensitive_keys`
+    let mut lower_case_keys: HashMap<String, String> = HashMap::new();
+    for (i, source_file) in source_files.iter().enumerate() {
+        for (k, v) in source_file.values.iter() {
+            let traced = trace.as_deref_mut().filter(|trace| trace.matches(k));
+            let existing_key = if options.case_insensitive_keys {
+                lower_case_keys.get(&k.trim().to_lowercase()).cloned()
+            } else {
+                Some(k.clone()).filter(|k| env_hash_map.contains_key(k))
+            };
+            if let Some(existing_key) = existing_key {
+                let origin = origins[&existing_key];
+                if origin != 0 || options.base_env.is_none() {
+                    let describe = || {
+                        (
+                            describe_key_location(&source_files[origin], &existing_key),
+                            describe_key_location(source_file, k),
+                        )
+                    };
+                    match options.on_conflict {
+                        OnConflict::Error => {
+                            let (first, second) = describe();
+                            return Err(YamlToEnvError::ConflictingKey {
+                                key: String::from(k.trim()),
+                                first,
+                                second,
+                            });
+                        }
+                        OnConflict::WarnLastWins => {
+                            let (first, second) = describe();
+                            warnings.push(Warning::ConflictingKey {
+                                key: String::from(k.trim()),
+                                first,
+                                second,
+                            });
+                        }
+                        OnConflict::FirstWins => {
+                            if let Some(trace) = traced {
+                                trace.record_ignored(&source_file.path, v, "first-wins");
+                            }
+                            continue;
+                        }
+                        OnConflict::LastWins => {}
+                    }
+                }
+                if existing_key != *k {
+                    env_hash_map.remove(&existing_key);
+                    origins.remove(&existing_key);
+                }
+            }
+            env_hash_map.insert(k.clone(), v.clone());
+            origins.insert(k.clone(), i);
+            if options.case_insensitive_keys {
+                lower_case_keys.insert(k.trim().to_lowercase(), k.clone());
+            }
+            if let Some(trace) = traced {
+                trace.record_merged(&source_file.path, v);
+            }
+        }
+    }
+    Ok((env_hash_map, origins))
+}
+
+/// Replace the values of `!env` tagged keys with the environment variable they refer to
+fn resolve_env_tags(
+    env_map: EnvMap,
+    env_tags: &HashMap<String, (Option<String>, Option<String>)>,
+) -> Result<EnvMap, YamlToEnvError> {
+    env_map
+        .into_iter()
+        .map(|(k, v)| match env_tags.get(&k) {
+            Some((var_name, default)) => {
+                let var_name = var_name.as_deref().unwrap_or(k.trim());
+                match (std::env::var(var_name), default) {
+                    (Ok(env_value), _) => Ok((k, env_value)),
+                    (Err(_), Some(default)) => Ok((k, default.clone())),
+                    (Err(_), None) => Err(YamlToEnvError::UnsetEnvTagVariable {
+                        var_name: String::from(var_name),
+                        key: String::from(k.trim()),
+                    }),
+                }
+            }
+            None => Ok((k, v)),
+        })
+        .collect()
+}
+
+/// Check whether a yaml scalar is a number
+fn is_numeric_scalar(value: &str) -> bool {
+    value.parse::<i64>().is_ok()
+        || (value.parse::<f64>().is_ok()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+}
+
+/// Check that every '%' in a value starts a two digit hex escape
+fn has_valid_percent_escapes(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.iter().enumerate().all(|(i, b)| {
+        *b != b'%'
+            || bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+    })
+}
+
+/// Percent-decode all values. Values with a malformed escape or that do not decode to valid
+/// UTF-8 are left as written, or fail if `strict` is set.
+fn url_decode_values(env_map: EnvMap, strict: bool) -> Result<EnvMap, YamlToEnvError> {
+    env_map
+        .into_iter()
+        .map(|(k, v)| {
+            let decoded = Some(&v)
+                .filter(|v| has_valid_percent_escapes(v))
+                .and_then(|v| percent_decode_str(v).decode_utf8().ok());
+            match decoded {
+                Some(decoded) => Ok((k, decoded.into_owned())),
+                None if !strict => Ok((k, v)),
+                None => Err(YamlToEnvError::InvalidPercentEncoding {
+                    key: String::from(k.trim()),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Keep only the keys listed in the allowlist, which must all be present unless a default
+/// value is given for the missing ones
+fn apply_allowlist(
+    env_map: EnvMap,
+    allowlist: &[String],
+    default_value: Option<&str>,
+    warnings: &mut Vec<Warning>,
+) -> Result<EnvMap, YamlToEnvError> {
+    let mut filtered_map = env_map
+        .into_iter()
+        .filter(|(k, _)| allowlist.iter().any(|key| key == k.trim()))
+        .collect::<EnvMap>();
+
+    let missing_keys = allowlist
+        .iter()
+        .map(String::as_str)
+        .filter(|key| !filtered_map.iter().any(|(k, _)| k.trim() == *key))
+        .collect::<Vec<&str>>();
+    match default_value {
+        Some(default_value) => {
+            for key in missing_keys {
+                warnings.push(Warning::DefaultedKey {
+                    key: String::from(key),
+                });
+                filtered_map.insert(String::from(key), String::from(default_value));
+            }
+        }
+        None if !missing_keys.is_empty() => {
+            return Err(YamlToEnvError::MissingAllowlistedKeys {
+                keys: missing_keys.into_iter().map(String::from).collect(),
+            });
+        }
+        None => {}
+    }
+
+    Ok(filtered_map)
+}
+
+/// Keep only the keys matching the pattern
+fn filter_keys_by_pattern(env_map: EnvMap, key_pattern: &Regex) -> EnvMap {
+    env_map
+        .into_iter()
+        .filter(|(k, _)| key_pattern.is_match(k.trim()))
+        .collect()
+}
+
+/// Warn about values containing newlines once trimmed as the env format writes them, as they
+/// break most .env parsers when written raw
+fn warn_on_multiline_values(env_map: &EnvMap, warnings: &mut Vec<Warning>) {
+    warnings.extend(
+        env_map
+            .iter()
+            .filter(|(_, v)| v.trim().contains('\n'))
+            .map(|(k, _)| Warning::MultilineValue {
+                key: String::from(k.trim()),
+            }),
+    );
+}
+
+/// Warn about keys and values whose surrounding whitespace is dropped on output
+fn warn_on_trimmed_whitespace(env_map: &EnvMap, warnings: &mut Vec<Warning>) {
+    env_map.iter().for_each(|(k, v)| {
+        let key = String::from(k.trim());
+        if k.trim() != k {
+            warnings.push(Warning::TrimmedKey { key: key.clone() });
+        }
+        if v.trim() != v {
+            warnings.push(Warning::TrimmedValue { key });
+        }
+    });
+}
+
+/// Quote the values of the map per the JS dotenv library's rules
+fn quote_values_for_dotenv(env_map: &EnvMap) -> Result<EnvMap, YamlToEnvError> {
+    env_map
+        .iter()
+        .map(|(k, v)| match quote_dotenv_value(v.trim()) {
+            Some(quoted) => Ok((k.clone(), quoted)),
+            None => Err(YamlToEnvError::DotenvUnrepresentable {
+                key: String::from(k.trim()),
+            }),
+        })
+        .collect()
+}
+
+/// Quote a value for the env format per `quote_mode`, escaping backslashes, double quotes and
+/// newlines inside the quotes
+pub(crate) fn quote_env_value(value: &str, quote_mode: QuoteMode) -> String {
+    let value = value.trim();
+    let needs_quotes = match quote_mode {
+        QuoteMode::Never => false,
+        QuoteMode::Always => true,
+        QuoteMode::Auto => value.contains([' ', '\t', '#', '=', '"', '\'', '`', '\n', '\r', '\\']),
+    };
+    if !needs_quotes {
+        return String::from(value);
+    }
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\"{}\"", escaped)
+}
+
+/// The env map as written by the env format, quoted and ordered per the options. The values
+/// of `quoted_strings` are written in double quotes whatever the quote mode, unless the
+/// dotenv library would not read them back unchanged.
+fn prepare_env_output(env_map: &EnvMap, options: &FormatOptions) -> Result<EnvMap, YamlToEnvError> {
+    let mut env_output_map = if options.dotenv_compat {
+        quote_values_for_dotenv(env_map)?
+    } else {
+        env_map
+            .iter()
+            .map(|(k, v)| (k.clone(), quote_env_value(v, options.quote_mode)))
+            .collect()
+    };
+    for (k, v) in env_map.iter() {
+        // dotenv expands no escapes but `\n` and `\r` in double quoted values
+        let keeps_value = !options.dotenv_compat || !v.contains(['"', '\\', '\n', '\r']);
+        if options.quoted_strings.contains(k) && keeps_value {
+            env_output_map.insert(k.clone(), quote_env_value(v, QuoteMode::Always));
+        }
+    }
+    if options.sort {
+        env_output_map.sort_keys();
+    }
+    Ok(env_output_map)
+}
+
+/// Convert env map to string, grouping keys under the file that set their final value, per
+/// `origins`. Keys no file set, e.g. defaulted allowlisted keys, come last.
+fn convert_map_to_grouped_string(
+    env_map: &EnvMap,
+    source_files: &[SourceFile],
+    origins: &HashMap<String, usize>,
+) -> String {
+    let mut groups = source_files
+        .iter()
+        .enumerate()
+        .filter_map(|(i, source_file)| {
+            let group = env_map
+                .iter()
+                .filter(|(k, _)| origins.get(*k) == Some(&i))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<EnvMap>();
+            if group.is_empty() {
+                None
+            } else {
+                Some(format!(
+                    "# from {}\n{}",
+                    source_file.path.display(),
+                    group.to_raw_assignments()
+                ))
+            }
+        })
+        .collect::<Vec<String>>();
+    let ungrouped = env_map
+        .iter()
+        .filter(|(k, _)| !origins.contains_key(*k))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect::<EnvMap>();
+    if !ungrouped.is_empty() {
+        groups.push(ungrouped.to_raw_assignments());
+    }
+    groups.join("\n")
+}
+
+/// Keep only the keys that are new or whose value differs from `previous`, returning them
+/// along with the keys of `previous` that are gone
+fn diff_against_previous(env_map: EnvMap, previous: &EnvMap) -> (EnvMap, Vec<String>) {
+    let mut removed_keys = previous
+        .iter()
+        .map(|(k, _)| k.trim())
+        .filter(|key| !env_map.iter().any(|(k, _)| k.trim() == *key))
+        .map(String::from)
+        .collect::<Vec<String>>();
+    removed_keys.sort();
+
+    let changed_map = env_map
+        .into_iter()
+        .filter(|(k, v)| previous.get(k.trim()).map(|p| p.trim()) != Some(v.trim()))
+        .collect();
+    (changed_map, removed_keys)
+}
+
+/// Read, merge and transform the yaml files into an env map
+pub fn parse(options: &ConvertOptions, paths: &[PathBuf]) -> Result<Parsed, YamlToEnvError> {
+    let mut warnings = Vec::new();
+    let mut cache = options.cache_path.as_deref().map(|cache_path| {
+        ParseCache::load(cache_path, options.parse_settings()).unwrap_or_else(|err| {
+            warnings.push(Warning::UnreadableCache {
+                reason: err.to_string(),
+            });
+            ParseCache::new(options.parse_settings())
+        })
+    });
+    let mut failed_paths = Vec::new();
+    // the base env is merged first so that every yaml file overrides it
+    let mut source_files = options
+        .base_env
+        .iter()
+        .cloned()
+        .collect::<Vec<SourceFile>>();
+    let mut trace = options
+        .explain_key
+        .as_deref()
+        .map(|key| KeyTrace::new(key, options.case_insensitive_keys));
+    source_files.extend(read_yaml_files(
+        paths,
+        cache.as_mut(),
+        options,
+        &mut failed_paths,
+        &mut warnings,
+        trace.as_mut(),
+    )?);
+    if let (Some(cache), Some(cache_path)) = (&cache, &options.cache_path) {
+        if let Err(err) = cache.save(cache_path) {
+            warnings.push(Warning::UnwritableCache {
+                reason: err.to_string(),
+            });
+        }
+    }
+
+    let (mut env_map, origins) =
+        create_env_hashmap(&source_files, options, &mut warnings, trace.as_mut())?;
+    if let Some(trace) = trace.as_mut() {
+        trace.record_merge(&env_map);
+    }
+    let mut record_step = |stage: &str, env_map: &EnvMap| {
+        if let Some(trace) = trace.as_mut() {
+            trace.record(stage, env_map);
+        }
+    };
+    // how the merged values were written, in the file each of them came from
+    let merged_scalars = origins
+        .iter()
+        .filter_map(|(k, origin)| Some((k, source_files[*origin].scalars.get(k)?)));
+    let mut string_keys = HashSet::new();
+    let mut quoted_keys = HashSet::new();
+    let mut env_tags = HashMap::new();
+    for (k, scalar) in merged_scalars {
+        match scalar {
+            YamlScalar::String(_) => {
+                string_keys.insert(k.clone());
+            }
+            YamlScalar::Quoted(_) => {
+                string_keys.insert(k.clone());
+                quoted_keys.insert(k.clone());
+            }
+            YamlScalar::EnvTag { var_name, default } => {
+                env_tags.insert(k.clone(), (var_name.clone(), default.clone()));
+            }
+            YamlScalar::Plain(_) => {}
+        }
+    }
+    env_map = resolve_env_tags(env_map, &env_tags)?;
+    record_step("env tag resolution", &env_map);
+    if options.interpolate {
+        // values taken from the environment or the base env are not yaml values to interpolate
+        let base_env_origin = options.base_env.as_ref().map(|_| 0);
+        let literal_keys = origins
+            .iter()
+            .filter(|(k, origin)| env_tags.contains_key(*k) || Some(**origin) == base_env_origin)
+            .map(|(k, _)| String::from(k.trim()))
+            .collect();
+        env_map = interpolate_values(env_map, options.allow_env_substitution, &literal_keys)?;
+        record_step("interpolation", &env_map);
+    }
+    if options.url_decode {
+        env_map = url_decode_values(env_map, options.url_decode_strict)?;
+        record_step("url decoding", &env_map);
+    }
+    if let Some(allowlist) = &options.allowlist {
+        env_map = apply_allowlist(
+            env_map,
+            allowlist,
+            options.default_value.as_deref(),
+            &mut warnings,
+        )?;
+        record_step("allowlist", &env_map);
+    }
+    if let Some(key_pattern) = &options.key_pattern {
+        env_map = filter_keys_by_pattern(env_map, key_pattern);
+        record_step("key pattern", &env_map);
+    }
+    if !options.allow_multiline && options.format.writes_raw_newlines() {
+        warn_on_multiline_values(&env_map, &mut warnings);
+    }
+    if options.warn_on_trim {
+        warn_on_trimmed_whitespace(&env_map, &mut warnings);
+    }
+    let mut removed_keys = Vec::new();
+    if let Some(previous) = &options.changed_since {
+        (env_map, removed_keys) = diff_against_previous(env_map, previous);
+        record_step("changes since the previous env file", &env_map);
+    }
+
+    let quoted_strings = env_map
+        .iter()
+        .filter(|(k, v)| {
+            (options.keep_string_quotes && quoted_keys.contains(*k))
+                || (!options.unquote_numbers
+                    && string_keys.contains(*k)
+                    && is_numeric_scalar(v.trim()))
+        })
+        .map(|(k, _)| k.clone())
+        .collect();
+
+    let yaml_layout = (options.format.output_format == OutputFormat::Yaml).then(|| {
+        let mut tree = Value::Mapping(Default::default());
+        for source_tree in source_files.iter().filter_map(|f| f.tree.clone()) {
+            merge_yaml_trees(&mut tree, source_tree);
+        }
+        YamlLayout {
+            tree,
+            settings: options.parse_settings(),
+        }
+    });
+
+    Ok(Parsed {
+        source_files,
+        failed_paths,
+        env_map,
+        origins,
+        removed_keys,
+        quoted_strings,
+        yaml_layout,
+        warnings,
+        trace: trace.map(KeyTrace::finish),
+    })
+}
+
+/// Format an env map, whether parsed from yaml files or built by the caller
+pub fn format(env_map: &EnvMap, options: &FormatOptions) -> Result<String, YamlToEnvError> {
+    let ordered = || {
+        let mut env_map = env_map.clone();
+        if options.sort {
+            env_map.sort_keys();
+        }
+        env_map
+    };
+    Ok(match options.output_format {
+        OutputFormat::Env => prepare_env_output(env_map, options)?.to_raw_assignments(),
+        OutputFormat::Shell => ordered().to_shell(),
+        OutputFormat::Docker => ordered().to_docker_args(),
+        OutputFormat::Jsonl => env_map.to_jsonl(),
+        OutputFormat::Hcl => env_map.to_hcl()?,
+        OutputFormat::Json => env_map.to_json(options.pretty),
+        OutputFormat::Toml => env_map.to_toml(),
+        OutputFormat::Yaml => match &options.yaml_layout {
+            Some(yaml_layout) => yaml_layout.to_yaml(env_map),
+            None => env_map.to_yaml(),
+        },
+    })
+}
+
+/// Builder reading yaml files into an env map, for use from other tools
+#[derive(Clone, Debug, Default)]
+pub struct EnvBuilder {
+    options: ConvertOptions,
+    paths: Vec<PathBuf>,
+}
+
+impl EnvBuilder {
+    /// Create a builder with no yaml files and the defaults of the command line tool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The settings to read the yaml files with
+    pub fn options(mut self, options: ConvertOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Add a yaml file, overriding the keys of the files added before it
+    pub fn add_yaml_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Read and merge the yaml files, dropping the warnings; use `parse` to get them
+    pub fn build(&self) -> Result<EnvMap, YamlToEnvError> {
+        Ok(parse(&self.options, &self.paths)?.env_map)
+    }
+}
+
+/// Parse the yaml files and format the resulting env map
+pub fn convert(options: &ConvertOptions, paths: &[PathBuf]) -> Result<Conversion, YamlToEnvError> {
+    let parsed = parse(options, paths)?;
+    let format_options = &options
+        .format
+        .clone()
+        .quoted_strings(parsed.quoted_strings.clone())
+        .yaml_layout(parsed.yaml_layout.clone());
+
+    let mut output = match format_options.output_format {
+        OutputFormat::Env if options.group_by_file => {
+            let env_output_map = prepare_env_output(&parsed.env_map, format_options)?;
+            convert_map_to_grouped_string(&env_output_map, &parsed.source_files, &parsed.origins)
+        }
+        _ => format(&parsed.env_map, format_options)?,
+    };
+    if format_options.output_format == OutputFormat::Env {
+        parsed
+            .removed_keys
+            .iter()
+            .for_each(|key| output.push_str(&format!("# removed: {}\n", key)));
+    }
+
+    Ok(Conversion {
+        source_files: parsed.source_files,
+        failed_paths: parsed.failed_paths,
+        env_map: parsed.env_map,
+        origins: parsed.origins,
+        warnings: parsed.warnings,
+        trace: parsed.trace,
+        output,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn warns_on_unquoted_multiline_values_unless_allowed() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "literal: |\n  first line\n  second line\n\
+             folded: >\n  first paragraph\n\n  second paragraph\n\
+             joined: >\n  first line\n  same line\n\
+             plain: x\n",
+        );
+        let options = ConvertOptions::new().quote_mode(QuoteMode::Never);
+
+        let parsed = parse(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(
+            parsed.env_map.get("folded").unwrap(),
+            "first paragraph\nsecond paragraph"
+        );
+        assert_eq!(
+            parsed.warnings,
+            vec![
+                Warning::MultilineValue {
+                    key: String::from("literal")
+                },
+                Warning::MultilineValue {
+                    key: String::from("folded")
+                }
+            ]
+        );
+
+        let parsed = parse(&options.allow_multiline(true), &[path]).unwrap();
+        assert!(parsed.warnings.is_empty());
+    }
+
+    #[test]
+    fn does_not_warn_on_a_single_line_block_scalar() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "single: |\n  one line\n");
+        let options = ConvertOptions::new().quote_mode(QuoteMode::Never);
+
+        let conversion = convert(&options, &[path]).unwrap();
+        assert!(conversion.warnings.is_empty());
+        assert_eq!(conversion.output, "single=one line\n");
+    }
+
+    #[test]
+    fn does_not_warn_on_multiline_values_that_are_quoted() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "folded: |\n  first line\n  second line\n");
+
+        let conversion = convert(&ConvertOptions::new(), &[path]).unwrap();
+        assert!(conversion.warnings.is_empty());
+        assert_eq!(conversion.output, "folded=\"first line\\nsecond line\"\n");
+    }
+
+    #[test]
+    fn keeps_quotes_of_numeric_strings_unless_unquoting() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "quoted: \"8080\"\nnumber: 8080\n");
+
+        let conversion = convert(&ConvertOptions::new(), std::slice::from_ref(&path)).unwrap();
+        assert_eq!(conversion.output, "quoted=\"8080\"\nnumber=8080\n");
+        assert_eq!(conversion.env_map.get("quoted").unwrap(), "8080");
+
+        let options = ConvertOptions::new().unquote_numbers(true);
+        let conversion = convert(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(conversion.output, "quoted=8080\nnumber=8080\n");
+    }
+
+    #[test]
+    fn keeps_quotes_of_quoted_strings_if_asked() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "'name': &name 'app'\nplain: app\nalias: *name\n\
+             db: {host: \"db\", port: 5432}\nhosts: [\"a\", b]\n\
+             limits: !json {zone: \"a\"}\n",
+        );
+        let options = ConvertOptions::new().keep_string_quotes(true);
+
+        let conversion = convert(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(
+            conversion.output,
+            "name=\"app\"\nplain=app\nalias=\"app\"\ndb_host=\"db\"\ndb_port=5432\n\
+             hosts_0=\"a\"\nhosts_1=b\nlimits=\"{\\\"zone\\\":\\\"a\\\"}\"\n"
+        );
+        assert_eq!(conversion.env_map.get("name").unwrap(), "app");
+
+        let options = options.output_format(OutputFormat::Yaml);
+        let conversion = convert(&options, std::slice::from_ref(&path)).unwrap();
+        assert!(conversion.output.contains("host: db\n"));
+        assert!(!conversion.output.contains("!quoted"));
+    }
+
+    #[test]
+    fn sorts_the_variables_by_key_only_if_asked() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "b: 2\na: 1\nc: 3\n");
+
+        let conversion = convert(&ConvertOptions::new(), std::slice::from_ref(&path)).unwrap();
+        assert_eq!(conversion.output, "b=2\na=1\nc=3\n");
+
+        let conversion = convert(&ConvertOptions::new().sort(true), &[path]).unwrap();
+        assert_eq!(conversion.output, "a=1\nb=2\nc=3\n");
+    }
+
+    #[test]
+    fn writes_numeric_strings_plain_in_other_formats() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "quoted: \"8080\"\nnumber: 8080\n");
+
+        let options = ConvertOptions::new().output_format(OutputFormat::Shell);
+        let conversion = convert(&options, &[path]).unwrap();
+        assert_eq!(
+            conversion.output,
+            "export quoted=\"8080\"\nexport number=\"8080\"\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn describes_paths_that_are_not_utf8_without_panicking() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = PathBuf::from(std::ffi::OsStr::from_bytes(b"/nonexistent/conf\xff.yaml"));
+
+        let err = parse(&ConvertOptions::new(), &[path]).unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/conf\u{fffd}.yaml"));
+    }
+
+    #[test]
+    fn reuses_cached_values_of_unchanged_files_only() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "key: 1\n");
+        let cache_path = dir.write("cache.json", "");
+        let options = ConvertOptions::new().cache_path(Some(cache_path.clone()));
+        parse(&options, std::slice::from_ref(&path)).unwrap();
+
+        // a value only the cache holds shows that the file was not parsed again
+        let cache = std::fs::read_to_string(&cache_path).unwrap();
+        assert!(cache.contains(r#""Plain":"1""#));
+        std::fs::write(
+            &cache_path,
+            cache.replace(r#""Plain":"1""#, r#""Plain":"9""#),
+        )
+        .unwrap();
+        let parsed = parse(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(parsed.env_map.get("key").unwrap(), "9");
+
+        std::fs::write(&path, "key: 22\n").unwrap();
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(parsed.env_map.get("key").unwrap(), "22");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_the_cache_readable_by_its_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "TOKEN: secret\n");
+        let cache_path = dir.write("cache.json", "");
+        std::fs::set_permissions(&cache_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let options = ConvertOptions::new().cache_path(Some(cache_path.clone()));
+
+        parse(&options, &[path]).unwrap();
+        let mode = std::fs::metadata(&cache_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn keeps_only_allowlisted_keys() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "kept: 1\ndropped: 2\n");
+        let options = ConvertOptions::new().allowlist(Some(vec![String::from("kept")]));
+
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "kept=1\n");
+    }
+
+    #[test]
+    fn fails_on_allowlisted_keys_missing_from_the_yaml_files() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "kept: 1\n");
+        let allowlist = vec![String::from("kept"), String::from("missing")];
+        let options = ConvertOptions::new().allowlist(Some(allowlist));
+
+        let err = parse(&options, &[path]).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::MissingAllowlistedKeys { keys } if keys == vec!["missing"]
+        ));
+    }
+
+    #[test]
+    fn reads_invalid_yaml_line_by_line_if_lenient() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "url: http://host: 80\nname: app\n");
+
+        let err = parse(&ConvertOptions::new(), std::slice::from_ref(&path)).unwrap_err();
+        assert!(matches!(err, YamlToEnvError::InvalidYaml { .. }));
+
+        let parsed = parse(&ConvertOptions::new().lenient_yaml(true), &[path]).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "url=\"http://host: 80\"\nname=app\n"
+        );
+        assert!(matches!(
+            parsed.warnings.as_slice(),
+            [Warning::LenientYaml { .. }]
+        ));
+    }
+
+    #[test]
+    fn decodes_percent_encoded_values() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "path: \"%2Fvar%2Flog\"\n");
+        let options = ConvertOptions::new().url_decode(true, true);
+
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(parsed.env_map.get("path").unwrap(), "/var/log");
+    }
+
+    #[test]
+    fn keeps_malformed_percent_escapes_unless_strict() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "ratio: \"50%zz\"\npath: \"%2Fa%zz\"\n");
+
+        let options = ConvertOptions::new().url_decode(true, false);
+        let parsed = parse(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(parsed.env_map.get("ratio").unwrap(), "50%zz");
+        assert_eq!(parsed.env_map.get("path").unwrap(), "%2Fa%zz");
+
+        let options = ConvertOptions::new().url_decode(true, true);
+        let err = parse(&options, &[path]).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::InvalidPercentEncoding { key } if key == "ratio"
+        ));
+    }
+
+    #[test]
+    fn fails_on_keys_nested_deeper_than_the_maximum_depth() {
+        let dir = TempDir::new();
+        let under = dir.write("under.yaml", "a:\n  b: 1\n");
+        let over = dir.write("over.yaml", "a:\n  b:\n    c: 1\n");
+        let options = ConvertOptions::new().max_depth(2);
+
+        let parsed = parse(&options, &[under]).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "a_b=1\n");
+
+        let err = parse(&options, &[over]).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::MaxDepthExceeded { key, max_depth: 2, .. } if key == "a_b_c"
+        ));
+    }
+
+    #[test]
+    fn reads_unnamed_env_tags_from_the_variable_named_like_the_key_before_renaming() {
+        std::env::set_var("YAML_TO_ENV_TEST_TAG_PREFIXED", "right");
+        std::env::set_var("APP_YAML_TO_ENV_TEST_TAG_PREFIXED", "wrong");
+        let dir = TempDir::new();
+        let path = dir.write("app.yaml", "YAML_TO_ENV_TEST_TAG_PREFIXED: !env\n");
+        let options = ConvertOptions::new().prefix_from_filename(true);
+
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "APP_YAML_TO_ENV_TEST_TAG_PREFIXED=right\n"
+        );
+    }
+
+    #[test]
+    fn reads_env_tagged_values_from_the_environment() {
+        std::env::set_var("YAML_TO_ENV_TEST_TAG_PORT", "8080");
+        std::env::remove_var("YAML_TO_ENV_TEST_TAG_UNSET");
+        let dir = TempDir::new();
+        let present = dir.write(
+            "present.yaml",
+            "port: !env YAML_TO_ENV_TEST_TAG_PORT\nYAML_TO_ENV_TEST_TAG_PORT: !env\n",
+        );
+        let defaulted = dir.write(
+            "defaulted.yaml",
+            "host: !env YAML_TO_ENV_TEST_TAG_UNSET:-localhost\n",
+        );
+        let absent = dir.write("absent.yaml", "host: !env YAML_TO_ENV_TEST_TAG_UNSET\n");
+
+        let parsed = parse(&ConvertOptions::new(), &[present]).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "port=8080\nYAML_TO_ENV_TEST_TAG_PORT=8080\n"
+        );
+
+        let parsed = parse(&ConvertOptions::new(), &[defaulted]).unwrap();
+        assert_eq!(parsed.env_map.get("host").unwrap(), "localhost");
+
+        let err = parse(&ConvertOptions::new(), &[absent]).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::UnsetEnvTagVariable { var_name, key }
+                if var_name == "YAML_TO_ENV_TEST_TAG_UNSET" && key == "host"
+        ));
+    }
+
+    #[test]
+    fn groups_keys_under_the_file_that_set_them() {
+        let dir = TempDir::new();
+        let app = dir.write("app.yaml", "name: app\nport: 80\n");
+        let local = dir.write("local.yaml", "port: 8080\ndebug: true\n");
+        let options = ConvertOptions::new().group_by_file(true);
+
+        let conversion = convert(&options, &[app.clone(), local.clone()]).unwrap();
+        assert_eq!(
+            conversion.output,
+            format!(
+                "# from {}\nname=app\n\n# from {}\nport=8080\ndebug=true\n",
+                app.display(),
+                local.display()
+            )
+        );
+    }
+
+    #[test]
+    fn writes_nested_sequences_as_compact_json() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "matrix:\n  - [1, 2]\n  - name: a\n    tags: [x, \"y\"]\n",
+        );
+        let options = ConvertOptions::new().array_mode(ArrayMode::Json);
+
+        let parsed = parse(&options, &[path]).unwrap();
+        let json = parsed.env_map.get("matrix").unwrap();
+        assert_eq!(json, r#"[[1,2],{"name":"a","tags":["x","y"]}]"#);
+        assert!(serde_json::from_str::<serde_json::Value>(json).is_ok());
+    }
+
+    #[test]
+    fn fails_on_sequences_with_no_json_form() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "hosts:\n  - ? [a, b]\n    : c\n");
+        let options = ConvertOptions::new().array_mode(ArrayMode::Json);
+
+        let err = parse(&options, &[path]).unwrap_err();
+        assert!(matches!(err, YamlToEnvError::UnsupportedStructure { .. }));
+    }
+
+    #[test]
+    fn joins_array_indices_with_the_array_separator() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "db:\n  hosts:\n    - name: a\n    - name: b\n  port: 5432\n",
+        );
+        let options = ConvertOptions::new()
+            .separator(String::from("_"))
+            .array_separator(Some(String::from("__")));
+
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "db_hosts__0_name=a\ndb_hosts__1_name=b\ndb_port=5432\n"
+        );
+    }
+
+    #[test]
+    fn builds_the_env_map_per_the_options() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "db:\n  hostName: a\nhosts: [x, y]\nHTTPServer: s\napiV2URL: u\n",
+        );
+
+        let env_map = EnvBuilder::new().add_yaml_file(&path).build().unwrap();
+        assert_eq!(
+            env_map.to_string(),
+            "db_hostName=a\nhosts_0=x\nhosts_1=y\nHTTPServer=s\napiV2URL=u\n"
+        );
+
+        let options = ConvertOptions::new()
+            .separator(String::from("__"))
+            .key_case(KeyCase::UpperSnake)
+            .array_mode(ArrayMode::Join);
+        let env_map = EnvBuilder::new()
+            .options(options)
+            .add_yaml_file(&path)
+            .build()
+            .unwrap();
+        assert_eq!(
+            env_map.to_string(),
+            "DB__HOST_NAME=a\nHOSTS=x,y\nHTTP_SERVER=s\nAPI_V2_URL=u\n"
+        );
+    }
+
+    #[test]
+    fn writes_only_the_keys_changed_since_the_previous_env_file() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "same: 1\nchanged: 3\nnew: 4\n");
+        let previous = parse_env_str("same=1\nchanged=2\ngone=5\n").unwrap();
+        let options = ConvertOptions::new().changed_since(Some(previous));
+
+        let conversion = convert(&options, &[path]).unwrap();
+        assert_eq!(conversion.output, "changed=3\nnew=4\n# removed: gone\n");
+    }
+
+    #[test]
+    fn warns_only_on_whitespace_that_is_trimmed() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "\" padded key \": 1\ntoken: \" secret \"\ninner: \"a b\"\nplain: x\n",
+        );
+        let options = ConvertOptions::new().warn_on_trim(true);
+
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(
+            parsed.warnings,
+            vec![
+                Warning::TrimmedKey {
+                    key: String::from("padded key")
+                },
+                Warning::TrimmedValue {
+                    key: String::from("token")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prefixes_keys_with_the_file_name() {
+        let dir = TempDir::new();
+        let database = dir.write("database.yaml", "host: db\n");
+        let app = dir.write("my-app.yaml", "host: app\n");
+        let options = ConvertOptions::new().prefix_from_filename(true);
+
+        let parsed = parse(&options, &[database, app]).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "DATABASE_host=db\nMY_APP_host=app\n"
+        );
+    }
+
+    #[test]
+    fn expands_embedded_dotenv_blocks() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "app:\n  legacy: !dotenv |\n    A=1\n    B=\"two words\"\n  name: x\n",
+        );
+
+        let parsed = parse(&ConvertOptions::new(), &[path]).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "app_A=1\napp_B=\"two words\"\napp_name=x\n"
+        );
+    }
+
+    #[test]
+    fn fails_on_invalid_lines_of_embedded_dotenv_blocks() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "legacy: !dotenv |\n  A=1\n  not an assignment\n");
+
+        let err = parse(&ConvertOptions::new(), &[path]).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::InvalidEmbeddedDotenv { key, line, .. }
+                if key == "legacy" && line == "not an assignment"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_keeping_their_name() {
+        let dir = TempDir::new();
+        let target = dir.write("target.yaml", "host: db\n");
+        let link = target.with_file_name("database.yaml");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let options = ConvertOptions::new().prefix_from_filename(true);
+
+        let parsed = parse(&options, &[link]).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "DATABASE_host=db\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fails_on_broken_symlinks() {
+        let dir = TempDir::new();
+        let target = dir.write("target.yaml", "");
+        let link = target.with_file_name("broken.yaml");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        std::fs::remove_file(&target).unwrap();
+
+        let err = parse(&ConvertOptions::new(), std::slice::from_ref(&link)).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::BrokenSymlink { path, target: broken_target }
+                if path == link && broken_target == target
+        ));
+    }
+
+    #[test]
+    fn merges_keys_differing_in_case_only_if_case_insensitive() {
+        let dir = TempDir::new();
+        let first = dir.write("first.yaml", "Path: /bin\n");
+        let second = dir.write("second.yaml", "PATH: /usr/bin\n");
+        let paths = [first, second];
+        let options = ConvertOptions::new().on_conflict(OnConflict::LastWins);
+
+        let parsed = parse(&options, &paths).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "Path=/bin\nPATH=/usr/bin\n");
+
+        let options = options.case_insensitive_keys(true);
+        let parsed = parse(&options, &paths).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "PATH=/usr/bin\n");
+
+        let options = options.on_conflict(OnConflict::Error);
+        let err = parse(&options, &paths).unwrap_err();
+        assert!(matches!(err, YamlToEnvError::ConflictingKey { key, .. } if key == "PATH"));
+    }
+
+    #[test]
+    fn returns_the_warnings_instead_of_printing_them() {
+        let dir = TempDir::new();
+        let first = dir.write("first.yaml", "name: a\nport: 80\n");
+        let second = dir.write("second.yaml", "# local\nport: 8080\n");
+        let options = ConvertOptions::new().on_conflict(OnConflict::WarnLastWins);
+
+        let parsed = parse(&options, &[first.clone(), second.clone()]).unwrap();
+        assert_eq!(
+            parsed.warnings,
+            vec![Warning::ConflictingKey {
+                key: String::from("port"),
+                first: format!("{}:2", first.display()),
+                second: format!("{}:2", second.display()),
+            }]
+        );
+    }
+
+    #[test]
+    fn locates_conflicting_keys_by_the_line_the_parser_read_them_on() {
+        let dir = TempDir::new();
+        let first = dir.write("first.yaml", "# db\ndb: {host: a, port: 1}\nhosts: [x]\n");
+        let second = dir.write("second.yaml", "db:\n  host: b\nhosts:\n  - y\n  - z\n");
+        let file_prefixes = [(first.clone(), "APP_"), (second.clone(), "APP_")]
+            .into_iter()
+            .map(|(path, prefix)| (path, String::from(prefix)))
+            .collect();
+        let options = ConvertOptions::new()
+            .on_conflict(OnConflict::WarnLastWins)
+            .file_prefixes(file_prefixes)
+            .key_case(KeyCase::UpperSnake);
+
+        let parsed = parse(&options, &[first.clone(), second.clone()]).unwrap();
+        let conflict = |key: &str, first_line: usize, second_line: usize| Warning::ConflictingKey {
+            key: String::from(key),
+            first: format!("{}:{}", first.display(), first_line),
+            second: format!("{}:{}", second.display(), second_line),
+        };
+        assert_eq!(
+            parsed.warnings,
+            vec![conflict("APP_DB_HOST", 2, 2), conflict("APP_HOSTS_0", 3, 4)]
+        );
+    }
+
+    #[test]
+    fn locates_the_keys_of_the_env_section_and_the_profile_kept() {
+        let dir = TempDir::new();
+        let first = dir.write(
+            "first.yaml",
+            "default:\n  port: 1\nproduction:\n  port: 2\n  port@dev: 3\n",
+        );
+        let second = dir.write("second.yaml", "production:\n  port: 4\n");
+        let options = ConvertOptions::new()
+            .on_conflict(OnConflict::WarnLastWins)
+            .env_section(Some(String::from("production")))
+            .profiles(vec![String::from("dev")]);
+
+        let parsed = parse(&options, &[first.clone(), second.clone()]).unwrap();
+        assert_eq!(
+            parsed.warnings,
+            vec![Warning::ConflictingKey {
+                key: String::from("port"),
+                first: format!("{}:5", first.display()),
+                second: format!("{}:2", second.display()),
+            }]
+        );
+    }
+
+    #[test]
+    fn selects_the_keys_marked_for_the_profile() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "DEBUG: false\nDEBUG@dev: true\nTRACE@prod: 1\nNAME: app\n",
+        );
+
+        let parsed = parse(&ConvertOptions::new(), std::slice::from_ref(&path)).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "DEBUG=false\nDEBUG@dev=true\nTRACE@prod=1\nNAME=app\n"
+        );
+
+        let options = ConvertOptions::new().profiles(vec![String::from("dev")]);
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "DEBUG=true\nNAME=app\n");
+    }
+
+    #[test]
+    fn merges_profile_sections_and_keeps_keys_that_are_not_marked() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "DATABASE:\n  host: a\n  port: 1\nDATABASE@dev:\n  host: x\nemail@example.com: y\n",
+        );
+
+        let options = ConvertOptions::new().profiles(vec![String::from("dev")]);
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "DATABASE_host=x\nDATABASE_port=1\nemail@example.com=y\n"
+        );
+    }
+
+    #[test]
+    fn formats_a_parsed_map_as_env_and_json() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "name: my app\nport: 80\n");
+        let parsed = parse(&ConvertOptions::new(), &[path]).unwrap();
+
+        let env = format(&parsed.env_map, &FormatOptions::new()).unwrap();
+        assert_eq!(env, "name=\"my app\"\nport=80\n");
+        let json = format(
+            &parsed.env_map,
+            &FormatOptions::new().output_format(OutputFormat::Json),
+        )
+        .unwrap();
+        let json = serde_json::from_str::<EnvMap>(&json).unwrap();
+        assert_eq!(json, parse_env_str(&env).unwrap());
+    }
+
+    #[test]
+    fn writes_json_tagged_values_as_json_and_other_values_plain() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "limits: !json\n  cpu: 2\n  zones: [a, b]\nname: app\nport: 80\n",
+        );
+
+        let parsed = parse(&ConvertOptions::new(), &[path]).unwrap();
+        let pairs = parsed
+            .env_map
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            vec![
+                ("limits", "{\"cpu\":2,\"zones\":[\"a\",\"b\"]}"),
+                ("name", "app"),
+                ("port", "80"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_only_the_keys_matching_the_pattern() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "db:\n  host: a\n  port: 1\ncache_host: b\nname: x\n",
+        );
+        let options = ConvertOptions::new().key_pattern(Some(Regex::new("_host$").unwrap()));
+
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "db_host=a\ncache_host=b\n");
+    }
+
+    #[test]
+    fn reads_no_as_a_boolean_only_in_yaml_1_1() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "country: no\nquoted: 'no'\nenabled: on\n");
+
+        let options = ConvertOptions::new().yaml_version(YamlVersion::V1_2);
+        let parsed = parse(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "country=no\nquoted=no\nenabled=on\n"
+        );
+
+        let options = ConvertOptions::new().yaml_version(YamlVersion::V1_1);
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "country=false\nquoted=no\nenabled=true\n"
+        );
+    }
+
+    #[test]
+    fn fills_allowlisted_keys_missing_from_the_yaml_files_with_the_default() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "kept: 1\n");
+        let allowlist = vec![String::from("kept"), String::from("missing")];
+        let options = ConvertOptions::new()
+            .allowlist(Some(allowlist))
+            .default_value(Some(String::from("changeme")));
+
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "kept=1\nmissing=changeme\n");
+        assert_eq!(
+            parsed.warnings,
+            vec![Warning::DefaultedKey {
+                key: String::from("missing")
+            }]
+        );
+    }
+
+    #[test]
+    fn writes_the_merged_tree_of_nested_yaml_files() {
+        let dir = TempDir::new();
+        let base = dir.write(
+            "base.yaml",
+            "server:\n  port: 80\n  host: a\ndb:\n  name: app\n",
+        );
+        let local = dir.write("local.yaml", "server:\n  port: 8080\n  tls: true\n");
+        let options = ConvertOptions::new().output_format(OutputFormat::Yaml);
+
+        let conversion = convert(&options, &[base, local]).unwrap();
+        assert_eq!(
+            conversion.output,
+            "db:\n  name: app\nserver:\n  host: a\n  port: 8080\n  tls: true\n"
+        );
+    }
+
+    #[test]
+    fn writes_the_filtered_and_interpolated_values_in_the_yaml_tree() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "server:\n  port: 80\n  host: a\n  url: http://${server_host}\ndb:\n  name: app\n",
+        );
+        let options = ConvertOptions::new()
+            .output_format(OutputFormat::Yaml)
+            .key_pattern(Some(Regex::new("^server_").unwrap()));
+
+        let conversion = convert(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(
+            conversion.output,
+            "server:\n  host: a\n  port: 80\n  url: http://a\n"
+        );
+        let parsed = parse(&options, &[path]).unwrap();
+        let format_options = FormatOptions::new()
+            .output_format(OutputFormat::Yaml)
+            .yaml_layout(parsed.yaml_layout);
+        assert_eq!(
+            format(&parsed.env_map, &format_options).unwrap(),
+            conversion.output
+        );
+    }
+
+    #[test]
+    fn writes_the_values_of_files_read_line_by_line_in_the_yaml_tree() {
+        let dir = TempDir::new();
+        let valid = dir.write("valid.yaml", "db:\n  name: app\n");
+        let invalid = dir.write("invalid.yaml", "url: http://host: 80\n");
+        let options = ConvertOptions::new()
+            .output_format(OutputFormat::Yaml)
+            .lenient_yaml(true);
+
+        let conversion = convert(&options, &[valid, invalid]).unwrap();
+        assert_eq!(
+            conversion.output,
+            "db:\n  name: app\nurl: 'http://host: 80'\n"
+        );
+    }
+
+    #[test]
+    fn traces_a_key_overridden_across_two_files() {
+        let dir = TempDir::new();
+        let base = dir.write("base.yaml", "host: a\nurl: http://a\n");
+        let local = dir.write("local.yaml", "host: b\nurl: http://${host}\n");
+        let options = ConvertOptions::new()
+            .interpolate(true)
+            .explain_key(Some(String::from("url")));
+
+        let parsed = parse(&options, &[base.clone(), local.clone()]).unwrap();
+        assert_eq!(
+            parsed.trace.unwrap(),
+            vec![
+                format!("set to http://a by {}", base.display()),
+                format!("overridden to http://${{host}} by {}", local.display()),
+                String::from("interpolation: changed to http://b"),
+                String::from("final value: http://b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn traces_the_value_kept_on_conflict_and_the_renames_of_the_key() {
+        let dir = TempDir::new();
+        let f1 = dir.write("f1.yaml", "dbHost: 1\n");
+        let f2 = dir.write("f2.yaml", "db-host: 9\n");
+        let options = ConvertOptions::new()
+            .on_conflict(OnConflict::FirstWins)
+            .key_case(KeyCase::UpperSnake)
+            .file_prefixes(HashMap::from([(f1.clone(), String::from("APP_"))]))
+            .explain_key(Some(String::from("APP_DB_HOST")));
+
+        let parsed = parse(&options, &[f1.clone(), f2.clone()]).unwrap();
+        assert_eq!(
+            parsed.trace.unwrap(),
+            vec![
+                String::from("key case: dbHost renamed to DB_HOST"),
+                String::from("prefix: DB_HOST renamed to APP_DB_HOST"),
+                format!("set to 1 by {}", f1.display()),
+                String::from("final value: 1"),
+            ]
+        );
+
+        let options = options
+            .file_prefixes(HashMap::new())
+            .explain_key(Some(String::from("DB_HOST")));
+        let parsed = parse(&options, &[f1.clone(), f2.clone()]).unwrap();
+        assert_eq!(
+            parsed.trace.unwrap(),
+            vec![
+                String::from("key case: dbHost renamed to DB_HOST"),
+                format!("set to 1 by {}", f1.display()),
+                String::from("key case: db-host renamed to DB_HOST"),
+                format!("ignored 9 from {} (first-wins)", f2.display()),
+                String::from("final value: 1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_the_yaml_files_over_the_base_env() {
+        let dir = TempDir::new();
+        let path = dir.write("a.yaml", "port: 8080\n");
+        let base_env = SourceFile::new(
+            PathBuf::from("base.env"),
+            parse_env_str("name=app\nport=80\n").unwrap(),
+        );
+        let options = ConvertOptions::new()
+            .on_conflict(OnConflict::Error)
+            .base_env(Some(base_env));
+
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(parsed.env_map.to_string(), "name=app\nport=8080\n");
+    }
+
+    #[test]
+    fn takes_the_values_of_the_selected_section_over_default_and_common() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "default:\n  host: localhost\n  port: 80\n  debug: false\n\
+             common:\n  port: 81\n  name: app\n\
+             prod:\n  host: db\n\
+             staging:\n  host: stage\n",
+        );
+        let options = ConvertOptions::new().env_section(Some(String::from("prod")));
+
+        let parsed = parse(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "host=db\nport=81\ndebug=false\nname=app\n"
+        );
+
+        let options = ConvertOptions::new().env_section(Some(String::from("dev")));
+        let err = parse(&options, &[path]).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::MissingEnvSection { env_section } if env_section == "dev"
+        ));
+    }
+
+    #[test]
+    fn interpolates_the_yaml_values_only() {
+        std::env::set_var("YAML_TO_ENV_TEST_INTERPOLATE_SECRET", "s${x");
+        std::env::set_var("YAML_TO_ENV_TEST_INTERPOLATE_HOST", "env-host");
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "host: db\nurl: http://${host}\n\
+             secret: !env YAML_TO_ENV_TEST_INTERPOLATE_SECRET\n",
+        );
+        let base_env = SourceFile::new(
+            PathBuf::from("base.env"),
+            parse_env_str("token='t${y'\n").unwrap(),
+        );
+        let options = ConvertOptions::new().base_env(Some(base_env));
+
+        let parsed = parse(&options, std::slice::from_ref(&path)).unwrap();
+        assert_eq!(parsed.env_map.get("url").unwrap(), "http://db");
+        assert_eq!(parsed.env_map.get("secret").unwrap(), "s${x");
+        assert_eq!(parsed.env_map.get("token").unwrap(), "t${y");
+
+        let parsed = parse(
+            &options.clone().interpolate(false),
+            std::slice::from_ref(&path),
+        )
+        .unwrap();
+        assert_eq!(parsed.env_map.get("url").unwrap(), "http://${host}");
+
+        let path = dir.write(
+            "b.yaml",
+            "url: http://${YAML_TO_ENV_TEST_INTERPOLATE_HOST}\n",
+        );
+        let err = parse(&ConvertOptions::new(), std::slice::from_ref(&path)).unwrap_err();
+        assert!(matches!(err, YamlToEnvError::UndefinedReference { .. }));
+        let options = ConvertOptions::new().allow_env_substitution(true);
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(parsed.env_map.get("url").unwrap(), "http://env-host");
+    }
+
+    #[test]
+    fn layers_the_profiles_in_order() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "region: none\nstage: none\nlevel: none\n\
+             region@default: local\nstage@default: dev\nlevel@default: debug\n\
+             region@region-eu: eu\nstage@region-eu: eu-dev\n\
+             stage@prod: prod\n",
+        );
+        let profiles = ["default", "region-eu", "prod"].map(String::from).to_vec();
+
+        let parsed = parse(
+            &ConvertOptions::new().profiles(profiles),
+            std::slice::from_ref(&path),
+        )
+        .unwrap();
+        assert_eq!(
+            parsed.env_map.to_string(),
+            "region=eu\nstage=prod\nlevel=debug\n"
+        );
+
+        let profiles = vec![String::from("prod"), String::from("staging")];
+        let err = parse(&ConvertOptions::new().profiles(profiles), &[path]).unwrap_err();
+        assert!(matches!(err, YamlToEnvError::UnusedProfile { profile } if profile == "staging"));
+    }
+
+    #[test]
+    fn drops_the_line_break_ending_a_certificate_block() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "cert: |\n  -----BEGIN-----\n  abc\n  -----END-----\nbundle: \"${cert}tail\"\n",
+        );
+
+        let parsed = parse(&ConvertOptions::new(), std::slice::from_ref(&path)).unwrap();
+        assert_eq!(
+            parsed.env_map.get("cert").unwrap(),
+            "-----BEGIN-----\nabc\n-----END-----"
+        );
+        assert_eq!(
+            parsed.env_map.get("bundle").unwrap(),
+            "-----BEGIN-----\nabc\n-----END-----tail"
+        );
+
+        let options = ConvertOptions::new().strip_trailing_value_newline(false);
+        let parsed = parse(&options, &[path]).unwrap();
+        assert_eq!(
+            parsed.env_map.get("bundle").unwrap(),
+            "-----BEGIN-----\nabc\n-----END-----\ntail"
+        );
+    }
+
+    #[test]
+    fn keeps_the_line_break_of_quoted_and_keep_chomped_values() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "quoted: \"line\\n\"\nkept: |+\n  line\n\nkept_once: |+ # as is\n  line\n\
+             folded: >\n  a\n  b\nsized: |2\n    indented\nlist:\n  - | # item\n\n    item\n",
+        );
+
+        let parsed = parse(&ConvertOptions::new(), &[path]).unwrap();
+        assert_eq!(parsed.env_map.get("quoted").unwrap(), "line\n");
+        assert_eq!(parsed.env_map.get("kept").unwrap(), "line\n\n");
+        assert_eq!(parsed.env_map.get("kept_once").unwrap(), "line\n");
+        assert_eq!(parsed.env_map.get("list_0").unwrap(), "\nitem");
+        assert_eq!(parsed.env_map.get("folded").unwrap(), "a b");
+        assert_eq!(parsed.env_map.get("sized").unwrap(), "  indented");
+    }
+}
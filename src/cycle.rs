@@ -0,0 +1,91 @@
+use std::fmt;
+
+/// Error for when an item is visited again while it is still being resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleError {
+    /// The visited items, starting and ending with the item that closes the cycle
+    pub chain: Vec<String>,
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cycle detected: {}", self.chain.join(" -> "))
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+/// Stack of the items currently being resolved, shared by anything that follows
+/// references (file includes, key references, ...) to catch cycles with a readable trace
+#[derive(Debug, Clone)]
+pub struct VisitStack<T> {
+    stack: Vec<T>,
+}
+
+impl<T> Default for VisitStack<T> {
+    fn default() -> Self {
+        Self { stack: Vec::new() }
+    }
+}
+
+impl<T: PartialEq + fmt::Display> VisitStack<T> {
+    /// Create an empty visit stack
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start visiting an item, failing with the full chain if it is already being visited
+    pub fn push(&mut self, item: T) -> Result<(), CycleError> {
+        if let Some(start) = self.stack.iter().position(|visited| *visited == item) {
+            let chain = self.stack[start..]
+                .iter()
+                .chain(std::iter::once(&item))
+                .map(|visited| visited.to_string())
+                .collect();
+            return Err(CycleError { chain });
+        }
+        self.stack.push(item);
+        Ok(())
+    }
+
+    /// Finish visiting the most recently pushed item
+    pub fn pop(&mut self) -> Option<T> {
+        self.stack.pop()
+    }
+
+    /// Whether an item is currently being visited
+    pub fn contains(&self, item: &T) -> bool {
+        self.stack.contains(item)
+    }
+
+    /// Number of items currently being visited
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traces_a_file_include_cycle() {
+        let mut stack = VisitStack::new();
+        stack.push("a.yaml").unwrap();
+        stack.push("b.yaml").unwrap();
+        let err = stack.push("a.yaml").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cycle detected: a.yaml -> b.yaml -> a.yaml"
+        );
+    }
+
+    #[test]
+    fn allows_revisiting_an_item_once_it_is_popped() {
+        let mut stack = VisitStack::new();
+        stack.push("a.yaml").unwrap();
+        assert_eq!(stack.pop(), Some("a.yaml"));
+        assert!(!stack.contains(&"a.yaml"));
+        stack.push("a.yaml").unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{EnvMap, YamlToEnvError};
+use indexmap::IndexMap;
+use std::collections::HashSet;
+
+/// A `KEY=value` assignment of a .env file
+struct EnvAssignment {
+    key: String,
+    /// The value without its quotes, escapes of double quoted values expanded
+    value: String,
+    /// The value as written, quotes included
+    raw_value: String,
+    export: bool,
+    /// The index of the line after the assignment, as quoted values may span several lines
+    end_line: usize,
+}
+
+/// Find the quote closing a value quoted with `quote`, skipping the backslash escapes of double
+/// quoted values
+fn find_closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            return Some(i);
+        } else if c == '\\' && quote == '"' {
+            chars.next();
+        }
+    }
+    None
+}
+
+/// Expand the `\n`, `\r`, `\"` and `\\` escapes of a double quoted value, keeping other
+/// backslashes as written
+fn unescape_double_quoted(text: &str) -> String {
+    let mut value = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some(escaped @ ('"' | '\\')) => value.push(escaped),
+            Some(other) => {
+                value.push('\\');
+                value.push(other);
+            }
+            None => value.push('\\'),
+        }
+    }
+    value
+}
+
+/// Read the assignment starting at line `start` of a .env file, or `None` for a blank line or
+/// a `#` comment. Single quoted and backtick quoted values are taken literally, double quoted
+/// ones have their escapes expanded, and unquoted ones are trimmed.
+fn read_assignment(lines: &[&str], start: usize) -> Option<Result<EnvAssignment, YamlToEnvError>> {
+    let line = lines[start].trim_start();
+    if line.trim_end().is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let invalid_line = || YamlToEnvError::InvalidEnvLine {
+        line_number: start + 1,
+        line: String::from(line.trim_end()),
+    };
+    let (export, assignment) = match line.strip_prefix("export ") {
+        Some(assignment) => (true, assignment),
+        None => (false, line),
+    };
+    let (key, value) = match assignment.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            (String::from(key.trim()), value.trim_start())
+        }
+        _ => return Some(Err(invalid_line())),
+    };
+    let quote = match value.chars().next() {
+        Some(quote @ ('"' | '\'' | '`')) => quote,
+        _ => {
+            let value = String::from(value.trim_end());
+            return Some(Ok(EnvAssignment {
+                key,
+                raw_value: value.clone(),
+                value,
+                export,
+                end_line: start + 1,
+            }));
+        }
+    };
+    // the quoted text, joined with the following lines until the closing quote
+    let mut text = String::from(&value[1..]);
+    let mut end_line = start + 1;
+    let close = loop {
+        if let Some(close) = find_closing_quote(&text, quote) {
+            break close;
+        }
+        match lines.get(end_line) {
+            Some(next_line) => {
+                text.push('\n');
+                text.push_str(next_line);
+                end_line += 1;
+            }
+            None => return Some(Err(invalid_line())),
+        }
+    };
+    let rest = text[close + 1..].trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Some(Err(invalid_line()));
+    }
+    let quoted = &text[..close];
+    Some(Ok(EnvAssignment {
+        key,
+        value: match quote {
+            '"' => unescape_double_quoted(quoted),
+            _ => String::from(quoted),
+        },
+        raw_value: format!("{}{}{}", quote, quoted, quote),
+        export,
+        end_line,
+    }))
+}
+
+/// Read the assignments of a .env file, later assignments of a key overriding earlier ones
+fn parse_env_assignments(content: &str) -> Result<IndexMap<String, EnvAssignment>, YamlToEnvError> {
+    let lines = content.lines().collect::<Vec<&str>>();
+    let mut assignments = IndexMap::new();
+    let mut i = 0;
+    while i < lines.len() {
+        match read_assignment(&lines, i) {
+            Some(assignment) => {
+                let assignment = assignment?;
+                i = assignment.end_line;
+                assignments.insert(assignment.key.clone(), assignment);
+            }
+            None => i += 1,
+        }
+    }
+    Ok(assignments)
+}
+
+/// Parse the content of a .env file, skipping blank lines and `#` comments. Quoted values are
+/// read back as the env format and `quote_dotenv_value` write them, possibly over several lines.
+pub fn parse_env_str(content: &str) -> Result<EnvMap, YamlToEnvError> {
+    Ok(parse_env_assignments(content)?
+        .into_iter()
+        .map(|(k, assignment)| (k, assignment.value))
+        .collect())
+}
+
+/// Quote a value so the JS `dotenv` library reads it back unchanged, following its rules:
+/// - an unquoted value is trimmed and ends at the first `#`, so values without surrounding
+///   whitespace, `#`, line breaks or a leading quote are written as they are
+/// - single quoted values are taken literally, so they are used next
+/// - double quoted values have `\n` and `\r` expanded, so they are only used when the value
+///   holds no backslash and no `"`, with line breaks written as `\n`
+/// - backtick quoted values are taken literally, used when the value already holds both `'`
+///   and `"`
+///
+/// Returns `None` for values that none of these forms can represent.
+pub fn quote_dotenv_value(value: &str) -> Option<String> {
+    let needs_quotes = value.trim() != value
+        || value.contains(['#', '\n', '\r'])
+        || value.starts_with(['\'', '"', '`']);
+    if !needs_quotes {
+        Some(String::from(value))
+    } else if !value.contains('\'') {
+        Some(format!("'{}'", value))
+    } else if !value.contains(['"', '\\']) {
+        Some(format!(
+            "\"{}\"",
+            value.replace('\n', "\\n").replace('\r', "\\r")
+        ))
+    } else if !value.contains('`') {
+        Some(format!("`{}`", value))
+    } else {
+        None
+    }
+}
+
+/// Update the assignments of an existing .env file with those of the generated one, written
+/// as they are quoted there, and append the new keys, keeping every other line as it is. The
+/// first assignment of a key is updated and its later assignments removed, as they would
+/// override it.
+pub fn merge_env_str(existing: &str, generated: &str) -> Result<String, YamlToEnvError> {
+    let generated_assignments = parse_env_assignments(generated)?;
+    let lines = existing.lines().collect::<Vec<&str>>();
+    let mut merged_keys = HashSet::new();
+    let mut merged = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        match read_assignment(&lines, i) {
+            Some(Ok(assignment)) => {
+                match generated_assignments.get(&assignment.key) {
+                    Some(generated) if merged_keys.insert(assignment.key.clone()) => {
+                        let export = if assignment.export { "export " } else { "" };
+                        merged.push_str(&format!(
+                            "{}{}={}\n",
+                            export, assignment.key, generated.raw_value
+                        ));
+                    }
+                    Some(_) => {}
+                    None => lines[i..assignment.end_line]
+                        .iter()
+                        .for_each(|line| merged.push_str(&format!("{}\n", line))),
+                }
+                i = assignment.end_line;
+            }
+            _ => {
+                merged.push_str(&format!("{}\n", lines[i]));
+                i += 1;
+            }
+        }
+    }
+    for (k, assignment) in generated_assignments.iter() {
+        if !merged_keys.contains(k) {
+            merged.push_str(&format!("{}={}\n", k, assignment.raw_value));
+        }
+    }
+    Ok(merged)
+}
+
+/// How an existing .env file compares with the generated one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvComparison {
+    /// The files are the same
+    Same,
+    /// The files set the same variables, with other comments or in another order
+    SameVariables,
+    /// The files set other variables, as `-KEY=value` lines for the existing assignments and
+    /// `+KEY=value` lines for the generated ones
+    Changed(Vec<String>),
+}
+
+/// Compare an existing .env file with the generated one
+pub fn compare_env_str(existing: &str, generated: &str) -> Result<EnvComparison, YamlToEnvError> {
+    if existing == generated {
+        return Ok(EnvComparison::Same);
+    }
+    let existing_map = parse_env_str(existing)?;
+    let generated_map = parse_env_str(generated)?;
+    if existing_map == generated_map {
+        return Ok(EnvComparison::SameVariables);
+    }
+    let mut diff = Vec::new();
+    for (k, v) in existing_map.iter() {
+        match generated_map.get(k) {
+            Some(generated_value) if generated_value == v => {}
+            Some(generated_value) => {
+                diff.push(format!("-{}={}", k, v));
+                diff.push(format!("+{}={}", k, generated_value));
+            }
+            None => diff.push(format!("-{}={}", k, v)),
+        }
+    }
+    for (k, v) in generated_map.iter() {
+        if !existing_map.contains_key(k) {
+            diff.push(format!("+{}={}", k, v));
+        }
+    }
+    Ok(EnvComparison::Changed(diff))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_values_per_the_dotenv_rules() {
+        let examples = [
+            ("basic", Some("basic")),
+            ("inner quotes \"here\"", Some("inner quotes \"here\"")),
+            (" padded ", Some("' padded '")),
+            ("not#comment", Some("'not#comment'")),
+            ("'quoted'", Some("\"'quoted'\"")),
+            ("line\nbreak", Some("'line\nbreak'")),
+            ("it's\nlines", Some("\"it's\\nlines\"")),
+            ("it's \"both\"", Some("it's \"both\"")),
+            ("#it's \"both\"", Some("`#it's \"both\"`")),
+            ("#it's \"all\" `three`", None),
+        ];
+        for (value, quoted) in examples {
+            assert_eq!(quote_dotenv_value(value).as_deref(), quoted, "{:?}", value);
+            if let Some(quoted) = quoted {
+                let env_map = parse_env_str(&format!("KEY={}\n", quoted)).unwrap();
+                assert_eq!(env_map.get("KEY").unwrap(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_quoted_values_back_unquoted() {
+        let content = "# comment\n\
+                       PLAIN=  a b  \n\
+                       export DOUBLE=\"say \\\"hi\\\"\\nC:\\\\dir\\x\" # note\n\
+                       SINGLE='literal \\n'\n\
+                       MULTI='first\n\
+                       second'\n\
+                       BACKTICK=`it's \"both\"`\n";
+
+        let env_map = parse_env_str(content).unwrap();
+        let pairs = env_map
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            vec![
+                ("PLAIN", "a b"),
+                ("DOUBLE", "say \"hi\"\nC:\\dir\\x"),
+                ("SINGLE", "literal \\n"),
+                ("MULTI", "first\nsecond"),
+                ("BACKTICK", "it's \"both\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_an_unclosed_quote() {
+        let err = parse_env_str("A=1\nB=\"open\nC=3\n").unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::InvalidEnvLine { line_number: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn merges_the_generated_values_as_they_are_written() {
+        let existing = "# managed by hand\nexport NAME=old\nCERT='a\nb'\nKEEP=1\n";
+        let generated = "NAME=\"my app\"\nCERT=\"x\\ny\"\nNEW=2\n";
+
+        assert_eq!(
+            merge_env_str(existing, generated).unwrap(),
+            "# managed by hand\nexport NAME=\"my app\"\nCERT=\"x\\ny\"\nKEEP=1\nNEW=2\n"
+        );
+    }
+
+    #[test]
+    fn compares_the_variables_of_env_files() {
+        let existing = "# app\nA=1\nB=2\n";
+
+        assert_eq!(
+            compare_env_str(existing, existing).unwrap(),
+            EnvComparison::Same
+        );
+        assert_eq!(
+            compare_env_str(existing, "B=2\nA='1'\n").unwrap(),
+            EnvComparison::SameVariables
+        );
+        assert_eq!(
+            compare_env_str(existing, "A=1\nB=3\nC=4\n").unwrap(),
+            EnvComparison::Changed(vec![
+                String::from("-B=2"),
+                String::from("+B=3"),
+                String::from("+C=4"),
+            ])
+        );
+        assert_eq!(
+            compare_env_str(existing, "B=2\n").unwrap(),
+            EnvComparison::Changed(vec![String::from("-A=1")])
+        );
+    }
+
+    #[test]
+    fn removes_later_assignments_of_a_merged_key() {
+        let existing = "x=0\nA=1\nx=5\n";
+        let generated = "x=1\n";
+
+        let merged = merge_env_str(existing, generated).unwrap();
+        assert_eq!(merged, "x=1\nA=1\n");
+        assert_eq!(parse_env_str(&merged).unwrap().get("x").unwrap(), "1");
+    }
+}
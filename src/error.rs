@@ -0,0 +1,121 @@
+use crate::CycleError;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Error of a conversion, naming the offending file or key
+#[derive(Error, Debug)]
+pub enum YamlToEnvError {
+    /// The config file could not be read
+    #[error("Could not read config file: {}", path.display())]
+    UnreadableConfig { path: PathBuf },
+    /// The config file is not valid for its config format
+    #[error("Invalid config file {}: {reason}", path.display())]
+    InvalidConfig { path: PathBuf, reason: String },
+    /// A line of a lines config file is too long to be a path
+    #[error(
+        "Line {line_number} of the config file is longer than the limit of {max_path_length} bytes"
+    )]
+    ConfigLineTooLong {
+        line_number: usize,
+        max_path_length: usize,
+    },
+    /// A config entry refers to an unset environment variable
+    #[error("Environment variable {var_name} used in config path {entry} is not set")]
+    UnsetPathVariable { var_name: String, entry: String },
+    /// A `${` in a config entry is never closed
+    #[error("Unclosed variable in config path {entry}")]
+    UnclosedPathVariable { entry: String },
+    /// A config entry is not a valid glob pattern
+    #[error("Invalid glob pattern {pattern}: {reason}")]
+    InvalidGlob { pattern: String, reason: String },
+    /// A glob pattern of the config matches no file
+    #[error("No files match the glob pattern {pattern}")]
+    NoGlobMatch { pattern: String },
+    /// A directory listed by the config could not be read
+    #[error("Could not read directory {}: {reason}", path.display())]
+    UnreadableDir { path: PathBuf, reason: String },
+    /// The config resolves to more yaml files than allowed
+    #[error("Config resolves to {file_count} yaml files, more than the limit of {max_files}")]
+    TooManyFiles { file_count: usize, max_files: usize },
+    /// A path of the config does not have the `.yaml` or `.yml` extension
+    #[error("All paths in config file must have .yaml or .yml extension, got {}", path.display())]
+    NotYamlFile { path: PathBuf },
+    /// A yaml file could not be read at all
+    #[error("Could not read yaml file with path: {}", path.display())]
+    UnreadableFile { path: PathBuf },
+    /// A yaml path is a symlink whose target does not exist
+    #[error("Yaml file path {} is a broken symlink to {}", path.display(), target.display())]
+    BrokenSymlink { path: PathBuf, target: PathBuf },
+    /// A yaml file could not be fetched from its URL
+    #[error("Could not fetch yaml file from URL {url}: {reason}")]
+    UnfetchableUrl { url: String, reason: String },
+    /// A yaml file is given as a URL but the `remote` feature is disabled
+    #[error("Cannot read yaml file from URL {url}: built without the remote feature")]
+    RemoteDisabled { url: String },
+    /// A yaml file is not valid yaml
+    #[error("Invalid yaml in file with path {}: {reason}", path.display())]
+    InvalidYaml { path: PathBuf, reason: String },
+    /// A yaml file holds something other than a mapping, or a mapping key is not a scalar
+    #[error("Unsupported yaml structure in file with path: {}", path.display())]
+    UnsupportedStructure { path: PathBuf },
+    /// A yaml key is nested deeper than the maximum depth
+    #[error(
+        "Key {key} in file with path {} is nested deeper than the maximum depth of {max_depth}",
+        path.display()
+    )]
+    MaxDepthExceeded {
+        path: PathBuf,
+        key: String,
+        max_depth: usize,
+    },
+    /// A line of a `!dotenv` tagged value is not a `KEY=value` assignment
+    #[error(
+        "Invalid line in the !dotenv value of key {key} in file with path {}: {line}",
+        path.display()
+    )]
+    InvalidEmbeddedDotenv {
+        path: PathBuf,
+        key: String,
+        line: String,
+    },
+    /// No yaml file has the selected env section
+    #[error("No yaml file has a top-level {env_section} section")]
+    MissingEnvSection { env_section: String },
+    /// A selected profile is not used by any yaml file
+    #[error("Profile '{profile}' is not used by any key of the yaml files")]
+    UnusedProfile { profile: String },
+    /// Two yaml files set the same key, the files being described as `path:line`
+    #[error("Key {key} is set by both {first} and {second}")]
+    ConflictingKey {
+        key: String,
+        first: String,
+        second: String,
+    },
+    /// A key tagged `!env` refers to an unset environment variable
+    #[error("Environment variable {var_name} required by key {key} is not set")]
+    UnsetEnvTagVariable { var_name: String, key: String },
+    /// A value is not validly percent-encoded
+    #[error("Invalid percent-encoding in value of key: {key}")]
+    InvalidPercentEncoding { key: String },
+    /// Keys of the allowlist are missing from the yaml files
+    #[error("Keys listed in allowlist file are missing from the yaml files: {}", keys.join(", "))]
+    MissingAllowlistedKeys { keys: Vec<String> },
+    /// A value cannot be quoted for the JS dotenv library
+    #[error("Value of key {key} contains every dotenv quote character and cannot be written")]
+    DotenvUnrepresentable { key: String },
+    /// A key cannot be written as an HCL attribute name
+    #[error("Key {key} is not a valid HCL identifier and cannot be written")]
+    InvalidHclKey { key: String },
+    /// A value refers to a key that is not defined
+    #[error("Key {key} refers to {name}, which is not defined")]
+    UndefinedReference { name: String, key: String },
+    /// A `${` in a value is never closed
+    #[error("Unclosed ${{ in the value of key {key}")]
+    UnclosedReference { key: String },
+    /// Values refer to each other in a cycle
+    #[error(transparent)]
+    Cycle(#[from] CycleError),
+    /// A line of a .env file is not a `KEY=value` assignment
+    #[error("Invalid line {line_number} in env file: {line}")]
+    InvalidEnvLine { line_number: usize, line: String },
+}
@@ -0,0 +1,137 @@
+use crate::EnvMap;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Steps that led to the final value of a single key, recorded for `--explain`
+#[derive(Debug)]
+pub(crate) struct KeyTrace {
+    key: String,
+    /// Whether keys differing only in case are the same variable
+    case_insensitive: bool,
+    value: Option<String>,
+    steps: Vec<String>,
+    /// The renames of the key in each file, noted once the merge reaches the file
+    renames: HashMap<PathBuf, Vec<String>>,
+}
+
+impl KeyTrace {
+    /// Start a trace of the key, matching keys differing only in case if `case_insensitive`
+    pub(crate) fn new(key: &str, case_insensitive: bool) -> Self {
+        Self {
+            key: String::from(key),
+            case_insensitive,
+            value: None,
+            steps: Vec::new(),
+            renames: HashMap::new(),
+        }
+    }
+
+    /// Whether a key read from a file is the traced key
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        if self.case_insensitive {
+            key.trim().to_uppercase() == self.key.to_uppercase()
+        } else {
+            key.trim() == self.key
+        }
+    }
+
+    /// The trimmed value of the traced key in a map
+    fn find_value(&self, env_map: &EnvMap) -> Option<String> {
+        env_map
+            .iter()
+            .find(|(k, _)| self.matches(k))
+            .map(|(_, v)| String::from(v.trim()))
+    }
+
+    /// Note how a key of a file was renamed into the traced key, first by the key case and
+    /// then by the file prefix
+    pub(crate) fn record_rename(
+        &mut self,
+        path: &Path,
+        read_key: &str,
+        cased_key: &str,
+        final_key: &str,
+    ) {
+        if !self.matches(final_key) {
+            return;
+        }
+        let renames = self.renames.entry(path.to_path_buf()).or_default();
+        if cased_key != read_key.trim() {
+            renames.push(format!(
+                "key case: {} renamed to {}",
+                read_key.trim(),
+                cased_key
+            ));
+        }
+        if final_key != cased_key {
+            renames.push(format!("prefix: {} renamed to {}", cased_key, final_key));
+        }
+    }
+
+    /// Note a value of the traced key that the merge kept, overriding any earlier one
+    pub(crate) fn record_merged(&mut self, path: &Path, value: &str) {
+        self.take_renames(path);
+        let verb = if self.value.is_none() {
+            "set"
+        } else {
+            "overridden"
+        };
+        self.steps.push(format!(
+            "{} to {} by {}",
+            verb,
+            value.trim(),
+            path.display()
+        ));
+        self.value = Some(String::from(value.trim()));
+    }
+
+    /// Note a value of the traced key that the merge dropped, keeping the earlier one
+    pub(crate) fn record_ignored(&mut self, path: &Path, value: &str, reason: &str) {
+        self.take_renames(path);
+        self.steps.push(format!(
+            "ignored {} from {} ({})",
+            value.trim(),
+            path.display(),
+            reason
+        ));
+    }
+
+    /// Move the renames of the key in a file to the steps
+    fn take_renames(&mut self, path: &Path) {
+        if let Some(renames) = self.renames.remove(path) {
+            self.steps.extend(renames);
+        }
+    }
+
+    /// Note the end of the merge, which may have set the key under another casing
+    pub(crate) fn record_merge(&mut self, env_map: &EnvMap) {
+        if self.steps.is_empty() {
+            self.steps.push(String::from("not set by any yaml file"));
+        }
+        self.record("merge", env_map);
+    }
+
+    /// Note the change made to the key by a stage of the conversion, if any
+    pub(crate) fn record(&mut self, stage: &str, env_map: &EnvMap) {
+        let value = self.find_value(env_map);
+        if value == self.value {
+            return;
+        }
+        let step = match (&self.value, &value) {
+            (_, Some(value)) => format!("{}: changed to {}", stage, value),
+            (Some(_), None) => format!("{}: removed", stage),
+            (None, None) => unreachable!(),
+        };
+        self.steps.push(step);
+        self.value = value;
+    }
+
+    /// The recorded steps followed by the final value
+    pub(crate) fn finish(mut self) -> Vec<String> {
+        self.steps.push(match &self.value {
+            Some(value) => format!("final value: {}", value),
+            None => String::from("not in the output"),
+        });
+        self.steps
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{EnvMap, VisitStack, YamlToEnvError};
+use std::collections::{HashMap, HashSet};
+
+/// Resolves the `${NAME}` references in values, following references to other keys first
+struct Interpolator<'a> {
+    /// The values before interpolation, by trimmed key
+    values: HashMap<&'a str, &'a str>,
+    /// The trimmed keys whose values are taken as written
+    literal_keys: &'a HashSet<String>,
+    allow_env: bool,
+    resolved: HashMap<String, String>,
+    stack: VisitStack<String>,
+}
+
+impl Interpolator<'_> {
+    /// The value of a key with its references replaced
+    fn resolve(&mut self, key: &str) -> Result<String, YamlToEnvError> {
+        if let Some(value) = self.resolved.get(key) {
+            return Ok(value.clone());
+        }
+        if self.literal_keys.contains(key) {
+            return Ok(String::from(self.values[key]));
+        }
+        self.stack.push(String::from(key))?;
+
+        let mut rest = self.values[key];
+        let mut value = String::new();
+        while let Some(start) = rest.find('$') {
+            value.push_str(&rest[..start]);
+            rest = &rest[start..];
+            // `$${` is an escaped `${`
+            if let Some(escaped) = rest.strip_prefix("$${") {
+                value.push_str("${");
+                rest = escaped;
+                continue;
+            }
+            let Some(reference) = rest.strip_prefix("${") else {
+                value.push('$');
+                rest = &rest[1..];
+                continue;
+            };
+            let end = reference
+                .find('}')
+                .ok_or_else(|| YamlToEnvError::UnclosedReference {
+                    key: String::from(key),
+                })?;
+            let name = reference[..end].trim();
+            let undefined_reference = || YamlToEnvError::UndefinedReference {
+                name: String::from(name),
+                key: String::from(key),
+            };
+            if self.values.contains_key(name) {
+                let referenced = self.resolve(name)?;
+                value.push_str(&referenced);
+            } else if self.allow_env {
+                let env_value = std::env::var(name).map_err(|_| undefined_reference())?;
+                value.push_str(&env_value);
+            } else {
+                return Err(undefined_reference());
+            }
+            rest = &reference[end + 1..];
+        }
+        value.push_str(rest);
+
+        self.stack.pop();
+        self.resolved.insert(String::from(key), value.clone());
+        Ok(value)
+    }
+}
+
+/// Replace `${NAME}` references in values by the value of the key `NAME`, or of the
+/// environment variable `NAME` if no key has that name and `allow_env` is set. `$${` is
+/// written as `${`. The values of `literal_keys`, e.g. those taken from the environment, are
+/// left as written, references to them included.
+pub(crate) fn interpolate_values(
+    env_map: EnvMap,
+    allow_env: bool,
+    literal_keys: &HashSet<String>,
+) -> Result<EnvMap, YamlToEnvError> {
+    let mut interpolator = Interpolator {
+        values: env_map
+            .iter()
+            .map(|(k, v)| (k.trim(), v.as_str()))
+            .collect(),
+        literal_keys,
+        allow_env,
+        resolved: HashMap::new(),
+        stack: VisitStack::new(),
+    };
+    env_map
+        .iter()
+        .map(|(k, _)| Ok((k.clone(), interpolator.resolve(k.trim())?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map of the given pairs, in order
+    fn env_map(pairs: &[(&str, &str)]) -> EnvMap {
+        pairs
+            .iter()
+            .map(|(k, v)| (String::from(*k), String::from(*v)))
+            .collect()
+    }
+
+    #[test]
+    fn traces_a_key_reference_cycle() {
+        let values = env_map(&[("A", "${B}"), ("B", "x${C}"), ("C", "${A}")]);
+        let err = interpolate_values(values, false, &HashSet::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Cycle detected: A -> B -> C -> A");
+    }
+
+    #[test]
+    fn replaces_references_by_the_values_they_name() {
+        let values = env_map(&[
+            ("HOST", "db"),
+            ("URL", "http://${HOST}:${ PORT }/$x"),
+            ("PORT", "80"),
+        ]);
+        let interpolated = interpolate_values(values, false, &HashSet::new()).unwrap();
+        assert_eq!(interpolated.get("URL").unwrap(), "http://db:80/$x");
+    }
+
+    #[test]
+    fn writes_escaped_references_as_they_are() {
+        let values = env_map(&[("A", "1"), ("B", "$${A} is ${A}")]);
+        let interpolated = interpolate_values(values, false, &HashSet::new()).unwrap();
+        assert_eq!(interpolated.get("B").unwrap(), "${A} is 1");
+    }
+
+    #[test]
+    fn fails_on_an_undefined_reference() {
+        let values = env_map(&[("A", "${MISSING_YAML_TO_ENV_KEY}")]);
+        let err = interpolate_values(values, false, &HashSet::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            YamlToEnvError::UndefinedReference { name, key }
+                if name == "MISSING_YAML_TO_ENV_KEY" && key == "A"
+        ));
+    }
+
+    #[test]
+    fn keeps_literal_values_as_written() {
+        let values = env_map(&[("SECRET", "a${b"), ("COPY", "${SECRET}")]);
+        let literal_keys = HashSet::from([String::from("SECRET")]);
+        let interpolated = interpolate_values(values, false, &literal_keys).unwrap();
+        assert_eq!(interpolated.get("SECRET").unwrap(), "a${b");
+        assert_eq!(interpolated.get("COPY").unwrap(), "a${b");
+    }
+}
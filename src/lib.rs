@@ -0,0 +1,384 @@
+mod cache;
+mod config;
+mod convert;
+mod cycle;
+mod dotenv;
+mod error;
+mod explain;
+mod interpolate;
+mod lint;
+#[cfg(feature = "remote")]
+mod remote;
+#[cfg(test)]
+mod test_util;
+mod warning;
+
+pub use config::{
+    has_yaml_extension, parse_config_entry, parse_inline_config, read_config_file,
+    resolve_config_entries, ConfigEntry, ConfigFormat, ResolvedPaths,
+};
+pub use convert::{
+    convert, format, parse, ArrayMode, Conversion, ConvertOptions, EnvBuilder, FormatOptions,
+    KeyCase, OnConflict, OnParseError, OutputFormat, Parsed, QuoteMode, SourceFile, YamlLayout,
+    YamlVersion,
+};
+pub use cycle::{CycleError, VisitStack};
+pub use dotenv::{
+    compare_env_str, merge_env_str, parse_env_str, quote_dotenv_value, EnvComparison,
+};
+pub use error::YamlToEnvError;
+pub use lint::{lint, LintCategory, LintIssue, LintLevel};
+pub use warning::Warning;
+
+use convert::quote_env_value;
+use indexmap::{map, IndexMap};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Map of env variable names to their values, as read from the yaml files, in the order
+/// the keys were first read
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct EnvMap(IndexMap<String, String>);
+
+impl EnvMap {
+    /// Create an empty env map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a key value pair, returning the previous value of the key if any
+    pub fn insert(&mut self, key: String, value: String) -> Option<String> {
+        self.0.insert(key, value)
+    }
+
+    /// Remove a key, returning its value if it was present, keeping the order of the others
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.shift_remove(key)
+    }
+
+    /// Get the value of a key
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.0.get(key)
+    }
+
+    /// Whether the map holds a key
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    /// Number of variables in the map
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether the map holds no variables
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterate over key value pairs
+    pub fn iter(&self) -> map::Iter<'_, String, String> {
+        self.0.iter()
+    }
+
+    /// Order the keys alphabetically, ignoring surrounding whitespace
+    pub fn sort_keys(&mut self) {
+        self.0.sort_by(|k1, _, k2, _| k1.trim().cmp(k2.trim()))
+    }
+
+    /// Trimmed key value pairs, sorted by key
+    pub fn sorted_pairs(&self) -> BTreeMap<&str, &str> {
+        self.iter().map(|(k, v)| (k.trim(), v.trim())).collect()
+    }
+
+    /// Format the map as `KEY=value` lines with the values written as they are, e.g. once
+    /// they are quoted for the env format
+    pub(crate) fn to_raw_assignments(&self) -> String {
+        self.iter()
+            .map(|(k, v)| format!("{}={}\n", k.trim(), v.trim()))
+            .collect()
+    }
+
+    /// Format the map as shell assignments, `export KEY="value"` per line
+    pub fn to_shell(&self) -> String {
+        self.iter()
+            .map(|(k, v)| {
+                format!(
+                    "export {}=\"{}\"\n",
+                    k.trim(),
+                    escape_shell_string(v.trim())
+                )
+            })
+            .collect()
+    }
+
+    /// Format the map as `docker run` arguments, `-e 'KEY=value'` per variable on one line
+    pub fn to_docker_args(&self) -> String {
+        let args = self
+            .iter()
+            .map(|(k, v)| {
+                let assignment = format!("{}={}", k.trim(), v.trim());
+                format!("-e '{}'", assignment.replace('\'', "'\\''"))
+            })
+            .collect::<Vec<String>>();
+        format!("{}\n", args.join(" "))
+    }
+
+    /// Format the map as JSON lines, one `{"key":"KEY","value":"value"}` object per line
+    /// sorted by key
+    pub fn to_jsonl(&self) -> String {
+        self.sorted_pairs()
+            .into_iter()
+            .map(|(key, value)| {
+                let line = serde_json::json!({ "key": key, "value": value });
+                format!("{}\n", line)
+            })
+            .collect()
+    }
+
+    /// Format the map as HCL attribute assignments, `KEY = "value"` per line sorted by key.
+    /// Fails on keys that are not HCL identifiers, e.g. `1bad` or `a.b`.
+    pub fn to_hcl(&self) -> Result<String, YamlToEnvError> {
+        self.sorted_pairs()
+            .into_iter()
+            .map(|(key, value)| {
+                if is_hcl_identifier(key) {
+                    Ok(format!("{} = \"{}\"\n", key, escape_hcl_string(value)))
+                } else {
+                    Err(YamlToEnvError::InvalidHclKey {
+                        key: String::from(key),
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Format the map as a JSON object sorted by key, indented if `pretty` is set
+    pub fn to_json(&self, pretty: bool) -> String {
+        let pairs = self.sorted_pairs();
+        let json = if pretty {
+            serde_json::to_string_pretty(&pairs)
+        } else {
+            serde_json::to_string(&pairs)
+        };
+        // a map of strings always serializes
+        format!("{}\n", json.unwrap())
+    }
+
+    /// Format the map as a TOML table sorted by key, using multi-line strings for values
+    /// spanning several lines
+    pub fn to_toml(&self) -> String {
+        // a map of strings always serializes
+        toml::to_string(&self.sorted_pairs()).unwrap()
+    }
+
+    /// Format the map as a YAML mapping sorted by key
+    pub fn to_yaml(&self) -> String {
+        // a map of strings always serializes
+        serde_yaml::to_string(&self.sorted_pairs()).unwrap()
+    }
+}
+
+/// Escape a value for use inside a double quoted shell string
+fn escape_shell_string(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+        .replace('`', "\\`")
+}
+
+/// Check whether a key is an HCL identifier, i.e. a letter or `_` followed by letters, digits,
+/// `_` and `-`
+fn is_hcl_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Escape a value for use inside a double quoted HCL string, including the `${` and `%{`
+/// template sequences
+fn escape_hcl_string(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t")
+        .replace("${", "$${")
+        .replace("%{", "%%{")
+}
+
+/// Formats the map as the content of a .env file, one `KEY=value` per line, quoting the values
+/// as the env format does with `QuoteMode::Auto`
+impl fmt::Display for EnvMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (k, v) in self.iter() {
+            writeln!(f, "{}={}", k.trim(), quote_env_value(v, QuoteMode::Auto))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<HashMap<String, String>> for EnvMap {
+    fn from(map: HashMap<String, String>) -> Self {
+        Self(map.into_iter().collect())
+    }
+}
+
+impl FromIterator<(String, String)> for EnvMap {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        Self(IndexMap::from_iter(iter))
+    }
+}
+
+impl Extend<(String, String)> for EnvMap {
+    fn extend<I: IntoIterator<Item = (String, String)>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+impl IntoIterator for EnvMap {
+    type Item = (String, String);
+    type IntoIter = map::IntoIter<String, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a EnvMap {
+    type Item = (&'a String, &'a String);
+    type IntoIter = map::Iter<'a, String, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map of the given pairs, in order
+    fn env_map(pairs: &[(&str, &str)]) -> EnvMap {
+        pairs
+            .iter()
+            .map(|(k, v)| (String::from(*k), String::from(*v)))
+            .collect()
+    }
+
+    #[test]
+    fn displays_trimmed_assignments_in_insertion_order() {
+        let map = env_map(&[("B", " 2 "), (" A", "1"), ("C", "two words\n\"quoted\"")]);
+        assert_eq!(
+            map.to_string(),
+            "B=2\nA=1\nC=\"two words\\n\\\"quoted\\\"\"\n"
+        );
+    }
+
+    #[test]
+    fn iterates_in_insertion_order() {
+        let map = env_map(&[("B", "2"), ("A", "1")]);
+        let keys = map.iter().map(|(k, _)| k.as_str()).collect::<Vec<&str>>();
+        assert_eq!(keys, vec!["B", "A"]);
+        let pairs = map.into_iter().collect::<Vec<(String, String)>>();
+        assert_eq!(pairs[1], (String::from("A"), String::from("1")));
+    }
+
+    #[test]
+    fn sorts_keys_ignoring_surrounding_whitespace() {
+        let mut map = env_map(&[("b", "2"), (" c", "3"), ("a", "1")]);
+        map.sort_keys();
+        assert_eq!(map.to_string(), "a=1\nb=2\nc=3\n");
+    }
+
+    #[test]
+    fn serializes_to_a_json_object() {
+        let map = env_map(&[("B", "2"), ("A", "1")]);
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"{"B":"2","A":"1"}"#);
+        assert_eq!(serde_json::from_str::<EnvMap>(&json).unwrap(), map);
+    }
+
+    #[test]
+    fn writes_one_json_object_per_variable() {
+        let map = env_map(&[("B", "two \"words\""), ("A", "1\n2")]);
+        let lines = map
+            .to_jsonl()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                serde_json::json!({ "key": "A", "value": "1\n2" }),
+                serde_json::json!({ "key": "B", "value": "two \"words\"" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_escaped_hcl_assignments() {
+        let map = env_map(&[
+            ("NAME", "say \"hi\"\\n"),
+            ("TEMPLATE", "${var}-%{if}\tend\nnext"),
+        ]);
+        assert_eq!(
+            map.to_hcl().unwrap(),
+            "NAME = \"say \\\"hi\\\"\\\\n\"\nTEMPLATE = \"$${var}-%%{if}\\tend\\nnext\"\n"
+        );
+    }
+
+    #[test]
+    fn writes_escaped_shell_assignments() {
+        let map = env_map(&[("A", r#"$HOME `id` "q" 'it' \n"#), ("B", " 1 ")]);
+        assert_eq!(
+            map.to_shell(),
+            "export A=\"\\$HOME \\`id\\` \\\"q\\\" 'it' \\\\n\"\nexport B=\"1\"\n"
+        );
+    }
+
+    #[test]
+    fn writes_docker_arguments_in_single_quotes() {
+        let map = env_map(&[("A", r#"$HOME `id` "q" 'it'"#), ("B", "1")]);
+        assert_eq!(
+            map.to_docker_args(),
+            "-e 'A=$HOME `id` \"q\" '\\''it'\\''' -e 'B=1'\n"
+        );
+    }
+
+    #[test]
+    fn rejects_keys_that_are_not_hcl_identifiers() {
+        let err = env_map(&[("1ST.KEY", "a")]).to_hcl().unwrap_err();
+        assert!(matches!(err, YamlToEnvError::InvalidHclKey { key } if key == "1ST.KEY"));
+    }
+
+    #[test]
+    fn writes_pretty_and_compact_json_of_the_same_value() {
+        let map = env_map(&[("B", "2"), ("A", "x")]);
+        let (pretty, compact) = (map.to_json(true), map.to_json(false));
+        assert_ne!(pretty, compact);
+        assert_eq!(
+            pretty.replace(char::is_whitespace, ""),
+            compact.replace(char::is_whitespace, "")
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+        );
+    }
+
+    #[test]
+    fn writes_a_toml_table_sorted_by_key() {
+        let map = env_map(&[("B", "first\nsecond"), ("A", "x")]);
+        let toml = map.to_toml();
+        assert_eq!(toml, "A = \"x\"\nB = \"\"\"\nfirst\nsecond\"\"\"\n");
+        assert_eq!(
+            toml::from_str::<EnvMap>(&toml).unwrap(),
+            env_map(&[("A", "x"), ("B", "first\nsecond")])
+        );
+    }
+}
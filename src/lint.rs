@@ -0,0 +1,253 @@
+use crate::convert::{read_yaml_document, yaml_key_to_string, YamlScalar};
+use crate::{ConvertOptions, YamlToEnvError};
+use clap::ArgEnum;
+use regex::Regex;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// How serious a lint issue is
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintLevel {
+    /// Worth a look, e.g. a value repeated under several keys
+    Info,
+    /// Likely a mistake, e.g. trailing whitespace in a value
+    Warning,
+    /// Breaks the generated env file, e.g. a key that is not a valid variable name
+    Error,
+}
+
+impl fmt::Display for LintLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintLevel::Info => write!(f, "info"),
+            LintLevel::Warning => write!(f, "warning"),
+            LintLevel::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The kind of a lint issue
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintCategory {
+    /// A flattened key is not a valid environment variable name
+    InvalidEnvName,
+    /// A value ends with whitespace
+    TrailingWhitespace,
+    /// A value ends with a colon, as if the rest of it was cut off
+    TruncatedValue,
+    /// A string value is also set by another key
+    DuplicateValue,
+    /// A key is nested deeper than the nesting limit
+    DeepNesting,
+}
+
+impl LintCategory {
+    /// How serious the issues of this category are
+    pub fn level(self) -> LintLevel {
+        match self {
+            LintCategory::InvalidEnvName => LintLevel::Error,
+            LintCategory::TrailingWhitespace
+            | LintCategory::TruncatedValue
+            | LintCategory::DeepNesting => LintLevel::Warning,
+            LintCategory::DuplicateValue => LintLevel::Info,
+        }
+    }
+}
+
+impl fmt::Display for LintCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintCategory::InvalidEnvName => write!(f, "invalid env names"),
+            LintCategory::TrailingWhitespace => write!(f, "trailing whitespace"),
+            LintCategory::TruncatedValue => write!(f, "likely truncated values"),
+            LintCategory::DuplicateValue => write!(f, "duplicate values"),
+            LintCategory::DeepNesting => write!(f, "deep nesting"),
+        }
+    }
+}
+
+/// A style issue of a key of a yaml file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintIssue {
+    pub category: LintCategory,
+    pub path: PathBuf,
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.path.display(), self.key, self.message)
+    }
+}
+
+/// Add the keys nested deeper than `max_nesting` mappings and sequences to `deep_keys`,
+/// without descending further into them
+fn find_deep_keys(
+    key: Option<&str>,
+    value: &Value,
+    depth: usize,
+    max_nesting: usize,
+    separator: &str,
+    deep_keys: &mut Vec<String>,
+) {
+    if depth > max_nesting {
+        deep_keys.extend(key.map(String::from));
+        return;
+    }
+    let join_key = |sub_key: &str| match key {
+        Some(key) => format!("{}{}{}", key, separator, sub_key),
+        None => String::from(sub_key),
+    };
+    match value {
+        Value::Mapping(mapping) => {
+            for (sub_key, sub_value) in mapping.iter() {
+                if let Some(sub_key) = yaml_key_to_string(sub_key) {
+                    let sub_key = join_key(&sub_key);
+                    find_deep_keys(
+                        Some(&sub_key),
+                        sub_value,
+                        depth + 1,
+                        max_nesting,
+                        separator,
+                        deep_keys,
+                    );
+                }
+            }
+        }
+        Value::Sequence(sequence) => {
+            for (i, item) in sequence.iter().enumerate() {
+                let sub_key = join_key(&i.to_string());
+                find_deep_keys(
+                    Some(&sub_key),
+                    item,
+                    depth + 1,
+                    max_nesting,
+                    separator,
+                    deep_keys,
+                );
+            }
+        }
+        Value::Tagged(tagged) => {
+            find_deep_keys(key, &tagged.value, depth, max_nesting, separator, deep_keys)
+        }
+        _ => {}
+    }
+}
+
+/// Check the keys and values of the yaml files for style issues, in input order: keys that
+/// are not valid environment variable names, values with trailing whitespace or ending in a
+/// colon, string values set by several keys, and keys nested deeper than `max_nesting`.
+/// Fails if a yaml file cannot be read or parsed.
+pub fn lint(
+    options: &ConvertOptions,
+    paths: &[PathBuf],
+    max_nesting: usize,
+) -> Result<Vec<LintIssue>, YamlToEnvError> {
+    let env_name = Regex::new("^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    let mut issues = Vec::new();
+    // the first key setting each string value, with its file
+    let mut first_keys: HashMap<String, (&Path, String)> = HashMap::new();
+    for path in paths.iter() {
+        let (values, document) = read_yaml_document(path, options)?;
+        let mut issue = |category: LintCategory, key: &str, message: String| {
+            issues.push(LintIssue {
+                category,
+                path: path.clone(),
+                key: String::from(key),
+                message,
+            })
+        };
+        for (k, scalar) in values.iter() {
+            let value = scalar.text();
+            if !env_name.is_match(k) {
+                issue(
+                    LintCategory::InvalidEnvName,
+                    k,
+                    String::from("not a valid environment variable name"),
+                );
+            }
+            if value.trim_end() != value {
+                issue(
+                    LintCategory::TrailingWhitespace,
+                    k,
+                    String::from("value ends with whitespace"),
+                );
+            }
+            if value.ends_with(':') {
+                issue(
+                    LintCategory::TruncatedValue,
+                    k,
+                    String::from("value ends with a colon and may be cut short"),
+                );
+            }
+            if let YamlScalar::String(value) | YamlScalar::Quoted(value) = scalar {
+                if value.trim().is_empty() {
+                    continue;
+                }
+                match first_keys.get(value) {
+                    Some((first_path, first_key)) => issue(
+                        LintCategory::DuplicateValue,
+                        k,
+                        format!("same value as {} in {}", first_key, first_path.display()),
+                    ),
+                    None => {
+                        first_keys.insert(value.clone(), (path, k.clone()));
+                    }
+                }
+            }
+        }
+        let mut deep_keys = Vec::new();
+        find_deep_keys(
+            None,
+            &document,
+            0,
+            max_nesting,
+            options.key_separator(),
+            &mut deep_keys,
+        );
+        for key in deep_keys {
+            issue(
+                LintCategory::DeepNesting,
+                &key,
+                format!("nested deeper than {} levels", max_nesting),
+            );
+        }
+    }
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn reports_each_category_of_issue() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "a.yaml",
+            "bad-key: x\ntoken: \"abc \"\nurl: \"http:\"\nfirst: same\nsecond: same\n\
+             deep:\n  a:\n    b: 1\n",
+        );
+
+        let issues = lint(&ConvertOptions::new(), std::slice::from_ref(&path), 2).unwrap();
+        let found = issues
+            .iter()
+            .map(|issue| (issue.category, issue.key.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (LintCategory::InvalidEnvName, "bad-key"),
+                (LintCategory::TrailingWhitespace, "token"),
+                (LintCategory::TruncatedValue, "url"),
+                (LintCategory::DuplicateValue, "second"),
+                (LintCategory::DeepNesting, "deep_a_b"),
+            ]
+        );
+        assert!(issues.iter().all(|issue| issue.path == path));
+    }
+}
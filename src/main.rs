@@ -1,138 +1,811 @@
-use clap::{Command, Parser};
-use std::collections::HashMap;
+mod summary;
+
+use clap::{ArgGroup, Command, Parser};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use regex::Regex;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use summary::RunSummary;
+use yaml_to_env::{
+    compare_env_str, merge_env_str, parse_env_str, parse_inline_config, read_config_file,
+    resolve_config_entries, ArrayMode, ConfigEntry, ConfigFormat, Conversion, ConvertOptions,
+    EnvComparison, EnvMap, KeyCase, LintLevel, OnConflict, OnParseError, OutputFormat, QuoteMode,
+    ResolvedPaths, SourceFile, YamlToEnvError, YamlVersion,
+};
+
+/// The yaml files to read, listed by a config or given as paths
+#[derive(clap::Args, Debug)]
+struct InputArgs {
+    /// The path to the input file with the paths to the yaml
+    #[clap(short = 'c', long = "config")]
+    #[clap(parse(from_os_str))]
+    config_path: Option<std::path::PathBuf>,
+    /// The paths to the yaml files, separated by commas or newlines, instead of a config file
+    #[clap(long = "config-inline")]
+    config_inline: Option<String>,
+    /// Yaml files, directories searched recursively for yaml files, or glob patterns, instead
+    /// of a config file
+    #[clap(parse(from_os_str))]
+    paths: Vec<std::path::PathBuf>,
+    /// How to interpret the config file, regardless of its name
+    #[clap(long = "config-format", arg_enum, default_value = "lines")]
+    config_format: ConfigFormat,
+    /// Fail before parsing anything if the config resolves to more yaml files than this
+    #[clap(long = "max-files")]
+    max_files: Option<usize>,
+    /// Fail on lines of a lines config file longer than this many bytes
+    #[clap(long = "max-path-length", default_value = "4096")]
+    max_path_length: usize,
+}
+
+/// Report style issues of the yaml files without converting them
+#[derive(clap::Args, Debug)]
+#[clap(group(
+    ArgGroup::new("lint-source")
+        .required(true)
+        .args(&["config-path", "config-inline", "paths"])
+))]
+struct LintArgs {
+    #[clap(flatten)]
+    input: InputArgs,
+    /// Exit non-zero if an issue of this level or above is found
+    #[clap(long = "error-level", arg_enum, default_value = "error")]
+    error_level: LintLevel,
+    /// Report keys nested deeper than this many mappings and sequences
+    #[clap(long = "max-nesting", default_value = "4")]
+    max_nesting: usize,
+}
+
+/// Commands run instead of the conversion
+#[derive(clap::Subcommand, Debug)]
+enum Action {
+    Lint(LintArgs),
+}
 
 /// Takes an input file with paths to yaml files with env source values and writes them to output path
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(group(
+    ArgGroup::new("config-source")
+        .required(true)
+        .args(&["config-path", "config-inline", "paths", "features"])
+))]
+#[clap(subcommand_negates_reqs = true)]
 struct Args {
-    /// The path to the input file with the paths to the yaml
-    #[clap(short = 'c', long = "config")]
+    #[clap(flatten)]
+    input: InputArgs,
+    /// The path to the output file, or `-` to write to stdout
+    #[clap(short = 'o', long = "output", required_unless_present = "features")]
     #[clap(parse(from_os_str))]
-    config_path: std::path::PathBuf,
-    /// The path to the output file
-    #[clap(short = 'o', long = "output")]
+    output_path: Option<std::path::PathBuf>,
+    /// Allow values spanning multiple lines without emitting a warning when they are written
+    /// unquoted with `--quote-mode never`
+    #[clap(long = "allow-multiline")]
+    allow_multiline: bool,
+    /// Write the yaml strings written in quotes in double quotes, e.g. `name: 'app'` becomes
+    /// `name="app"` (env format only). Yaml strings holding a number are quoted by default.
+    #[clap(long = "keep-string-quotes", conflicts_with = "unquote-numbers")]
+    keep_string_quotes: bool,
+    /// Write yaml strings holding a number without quotes, e.g. `port: "8080"` becomes
+    /// `port=8080`
+    #[clap(long = "unquote-numbers")]
+    unquote_numbers: bool,
+    /// The path to a cache file used to skip reparsing yaml files that did not change. It holds
+    /// the values in plain text and is written readable by its owner only.
+    #[clap(long = "cache")]
     #[clap(parse(from_os_str))]
-    output_path: std::path::PathBuf,
+    cache_path: Option<std::path::PathBuf>,
+    /// The path to a file listing the only keys to output, one per line
+    #[clap(long = "only")]
+    #[clap(parse(from_os_str))]
+    allowlist_path: Option<std::path::PathBuf>,
+    /// Output keys listed with `--only` but missing from the yaml files with this value,
+    /// instead of failing
+    #[clap(long = "default-value", requires = "allowlist-path")]
+    default_value: Option<String>,
+    /// The format of the output file
+    #[clap(
+        long = "output-format",
+        alias = "format",
+        arg_enum,
+        default_value = "env"
+    )]
+    output_format: OutputFormat,
+    /// Print a JSON summary of the run to stdout instead of the success message
+    #[clap(long = "summary-json")]
+    summary_json: bool,
+    /// Percent-decode values, e.g. `%2Fvar%2Flog` becomes `/var/log`
+    #[clap(long = "url-decode")]
+    url_decode: bool,
+    /// Fail on malformed percent escapes instead of leaving the value unchanged
+    #[clap(long = "url-decode-strict", requires = "url-decode")]
+    url_decode_strict: bool,
+    /// Group variables under a comment naming the yaml file they were taken from (env format only)
+    #[clap(long = "group-by-file")]
+    group_by_file: bool,
+    /// What to do when a yaml file cannot be read or parsed
+    #[clap(long = "on-parse-error", arg_enum, default_value = "abort")]
+    on_parse_error: OnParseError,
+    /// What to do when several yaml files set the same key
+    #[clap(long = "on-conflict", arg_enum, default_value = "last-wins")]
+    on_conflict: OnConflict,
+    /// The path to a previously generated env file; only new and changed keys are written
+    #[clap(long = "changed-since")]
+    #[clap(parse(from_os_str))]
+    changed_since_path: Option<std::path::PathBuf>,
+    /// Treat the output path as a directory and write each variable to a file named after it
+    #[clap(long = "split-files", conflicts_with = "group-by-file")]
+    split_files: bool,
+    /// Warn when trimming whitespace on output changes a key or value
+    #[clap(long = "warn-on-trim")]
+    warn_on_trim: bool,
+    /// Prefix the keys of each yaml file with its uppercased file name, e.g. `DATABASE_`,
+    /// unless the config declares a prefix for the file with `path -> PREFIX_`
+    #[clap(long = "prefix-from-filename")]
+    prefix_from_filename: bool,
+    /// Pretty-print the json output format
+    #[clap(long = "pretty")]
+    pretty: bool,
+    /// A shell command the generated content is piped through before it is written
+    #[clap(long = "post-process", conflicts_with = "split-files")]
+    post_process: Option<String>,
+    /// Treat keys differing only in case, e.g. `Path` and `PATH`, as the same variable as
+    /// Windows does, keeping the casing written last. Keys are case-sensitive by default.
+    #[clap(long = "case-insensitive-keys")]
+    case_insensitive_keys: bool,
+    /// Time limit in seconds for fetching each yaml file given as an http:// or https:// URL,
+    /// 30 by default
+    #[clap(long = "timeout")]
+    timeout: Option<u64>,
+    /// Quote values so the JS dotenv library reads them back unchanged: plain when safe, else
+    /// single quotes, double quotes (no backslashes) or backticks (env format only)
+    #[clap(long = "dotenv-compat")]
+    dotenv_compat: bool,
+    /// When to wrap values of the env format in double quotes; ignored with `--dotenv-compat`
+    #[clap(long = "quote-mode", arg_enum, default_value = "auto")]
+    quote_mode: QuoteMode,
+    /// Include keys marked `KEY@<PROFILE>` for this profile, as `KEY`; keys marked for other
+    /// profiles are left out. Repeat to layer profiles, later ones winning.
+    #[clap(long = "profile")]
+    profiles: Vec<String>,
+    /// Log the variables read from each yaml file to stderr, key names only unless
+    /// `--no-redact-log` is given
+    #[clap(short = 'v', long = "verbose")]
+    verbose: bool,
+    /// Log the values along with the key names in the verbose log
+    #[clap(long = "no-redact-log", requires = "verbose")]
+    no_redact_log: bool,
+    /// Output only the keys matching this regular expression
+    #[clap(long = "grep")]
+    grep: Option<Regex>,
+    /// Print per-file variable counts, overrides and parse times to stderr
+    #[clap(long = "stats")]
+    stats: bool,
+    /// Yaml spec version deciding whether `yes`, `no`, `on` and `off` are booleans
+    #[clap(long = "yaml-version", arg_enum, default_value = "1.2")]
+    yaml_version: YamlVersion,
+    /// Print how this key gets its final value instead of writing the output
+    #[clap(long = "explain")]
+    explain: Option<String>,
+    /// The path to an env file whose variables the yaml files are merged over
+    #[clap(long = "base-env")]
+    #[clap(parse(from_os_str))]
+    base_env_path: Option<std::path::PathBuf>,
+    /// Octal mode to give the written files, e.g. `600` (Unix only)
+    #[clap(long = "chmod", parse(try_from_str = parse_file_mode))]
+    chmod: Option<u32>,
+    /// Separator joining the keys of nested yaml mappings into one variable name
+    #[clap(long = "separator", default_value = "_")]
+    separator: String,
+    /// Separator joining the indices of yaml sequences to their key in `index` array mode,
+    /// e.g. `__` for `HOSTS__0`; the `--separator` by default
+    #[clap(long = "array-separator")]
+    array_separator: Option<String>,
+    /// How yaml sequences are written: one variable per item (`index`), one variable with the
+    /// items joined with commas (`join`) or one variable holding the sequence as JSON (`json`)
+    #[clap(long = "array-mode", arg_enum, default_value = "index")]
+    array_mode: ArrayMode,
+    /// Fail on yaml keys nested deeper than this many mappings and sequences
+    #[clap(long = "max-depth", default_value = "64")]
+    max_depth: usize,
+    /// Sort the variables of the env, shell and docker formats by key instead of keeping the
+    /// order they were read in
+    #[clap(long = "sort")]
+    sort: bool,
+    /// Take the variables from this top-level section of each yaml file, e.g. `production`,
+    /// over those of a `default` or `common` section
+    #[clap(long = "env")]
+    env_section: Option<String>,
+    /// Keep `${NAME}` in values as written instead of replacing it by the value of key `NAME`
+    #[clap(long = "no-interpolate")]
+    no_interpolate: bool,
+    /// Replace `${NAME}` by the environment variable `NAME` when no key has that name
+    #[clap(long = "allow-env-substitution", conflicts_with = "no-interpolate")]
+    allow_env_substitution: bool,
+    /// Compare the output with the existing output file and exit non-zero if they differ,
+    /// printing the differing keys instead of writing the file
+    #[clap(long = "check", conflicts_with = "split-files")]
+    check: bool,
+    /// Update the keys of the existing output file and append new ones, keeping its other
+    /// lines and comments, instead of overwriting it (env format only)
+    #[clap(long = "merge", conflicts_with = "split-files")]
+    merge: bool,
+    /// Keep running and write the output again whenever the config file or a yaml file
+    /// changes
+    #[clap(long = "watch", conflicts_with_all = &["check", "explain"])]
+    watch: bool,
+    /// Casing of the keys read from the yaml files, applied before any file prefix
+    #[clap(long = "key-case", arg_enum, default_value = "preserve")]
+    key_case: KeyCase,
+    /// Print the optional features this binary was built with and exit
+    #[clap(long = "features")]
+    features: bool,
+    /// Read yaml files that fail to parse line by line as `key: value` pairs, with a warning,
+    /// instead of failing
+    #[clap(long = "lenient-yaml")]
+    lenient_yaml: bool,
+    /// Keep the line break ending `|` and `>` block scalars instead of dropping it
+    #[clap(long = "no-strip-trailing-value-newline")]
+    no_strip_trailing_value_newline: bool,
+    #[clap(subcommand)]
+    action: Option<Action>,
 }
 
-const CONFIG_READ_ERROR_MESSAGE: &str = "Could not read config file";
-const NOT_YAML_FILE_PATH_ERROR_MESSAGE: &str = "All paths in config file must have .yaml extension";
+/// How long to wait for further changes before converting again
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+/// Read the keys of the allowlist file, one per line
+fn read_allowlist_file(path: &Path, cmd: &mut Command) -> Result<Vec<String>, clap::Error> {
+    let allowlist = std::fs::read_to_string(path).map_err(|err| {
+        cmd.error(
+            clap::ErrorKind::Io,
+            format!("Could not read allowlist file: {}", err),
+        )
+    })?;
+    Ok(allowlist
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect())
+}
 
-/// Create an error for when yaml file could not be read at all
-fn create_yaml_file_read_err(path: &PathBuf) -> String {
-    return format!(
-        "Could not read yaml file with path: {err_file_path}",
-        err_file_path = path.clone().into_os_string().into_string().unwrap()
-    );
+/// Read the variables of a previously generated env file
+fn read_env_file(path: &Path, cmd: &mut Command) -> Result<EnvMap, clap::Error> {
+    let content = std::fs::read_to_string(path).map_err(|err| {
+        cmd.error(
+            clap::ErrorKind::Io,
+            format!("Could not read env file {}: {}", path.display(), err),
+        )
+    })?;
+    parse_env_str(&content).map_err(|msg| cmd.error(clap::ErrorKind::ValueValidation, msg))
 }
 
-/// Create an error for when yaml content has invalid format
-fn create_yaml_content_validation_err(path: &PathBuf) -> String {
-    return format!(
-        "Unsupported yaml structure in file with path: {err_file_path}",
-        err_file_path = path.clone().into_os_string().into_string().unwrap()
-    );
+/// Parse a file mode written in octal, e.g. `600`
+fn parse_file_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("{} is not an octal file mode", mode))
 }
 
-/// Read all paths to the input yaml files from the config file
-fn read_config_file(path: &PathBuf) -> Vec<PathBuf> {
-    return std::fs::read_to_string(path)
-        .expect(CONFIG_READ_ERROR_MESSAGE)
-        .lines()
-        .map(|l| PathBuf::from(l))
-        .collect::<Vec<PathBuf>>();
+#[cfg(unix)]
+fn set_file_mode(file: &File, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
 }
 
-/// Ensure all paths end in '.yaml'
-fn assert_paths_are_yaml_files(
-    paths: Vec<PathBuf>,
-    cmd: &mut Command,
-) -> Result<Vec<PathBuf>, clap::Error> {
-    if paths.iter().all(|path| path.extension().unwrap() == "yaml") {
-        return Ok(paths);
-    } else {
-        println!("{:?}", paths);
-        let err: clap::Error = cmd.error(
-            clap::ErrorKind::ValueValidation,
-            NOT_YAML_FILE_PATH_ERROR_MESSAGE,
-        );
-        return Err(err);
+#[cfg(not(unix))]
+fn set_file_mode(_file: &File, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn write_env_file(
+    output_path: &Path,
+    output_content: &str,
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    let mut file = File::create(output_path)?;
+    // restrict the file before any secret is written to it
+    if let Some(mode) = mode {
+        set_file_mode(&file, mode)?;
     }
+    file.write_all(output_content.as_bytes())?;
+    Ok(())
 }
 
-/// Read yaml files and add values to env hashmap
-fn create_env_hashmap(
-    paths: Vec<PathBuf>,
-    cmd: &mut Command,
-) -> Result<HashMap<String, String>, clap::Error> {
-    let mut env_hash_map = HashMap::new();
+/// Compare the generated content with the existing output file, printing how they differ.
+/// Returns whether the file is up to date.
+fn check_output_file(output_path: &Path, content: &str, output_format: OutputFormat) -> bool {
+    let existing = std::fs::read_to_string(output_path).unwrap_or_default();
+    if existing == content {
+        return true;
+    }
+    let report = match compare_env_str(&existing, content) {
+        Ok(EnvComparison::Changed(diff)) if output_format == OutputFormat::Env => diff
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect::<String>(),
+        Ok(_) if output_format == OutputFormat::Env => format!(
+            "{} differs only in comments or order\n",
+            output_path.display()
+        ),
+        _ => format!("{} is not up to date\n", output_path.display()),
+    };
+    print_stdout(&report);
+    false
+}
 
-    for path in paths.iter() {
-        // read file
-        let file = std::fs::read_to_string(path).expect(create_yaml_file_read_err(path).as_str());
+/// Write the generated content to stdout
+fn write_stdout(output_content: &str) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(output_content.as_bytes())?;
+    stdout.flush()
+}
 
-        // read yaml lines and transform into collection of key value pairs
-        let parsed_line_key_val_pairs = file.lines().map(|l| {
-            if l.starts_with('#') {
-                return Some((String::from(""), String::from("")));
-            }
-            match l.split_once(':') {
-                Some((key, value)) => Some((String::from(key), String::from(value))),
-                None => None,
-            }
-        });
-
-        // throw err if any line could not be split into two on ':' char
-        if parsed_line_key_val_pairs.clone().any(|res| res.is_none()) {
-            let err: clap::Error = cmd.error(
-                clap::ErrorKind::ValueValidation,
-                create_yaml_content_validation_err(path),
-            );
-            return Err(err);
+/// Print a report to stdout, ignoring a reader that went away, e.g. `head`
+fn print_stdout(report: &str) {
+    match write_stdout(report) {
+        Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => {
+            eprintln!("Error when trying to write to stdout: {}", err);
+            std::process::exit(1);
         }
+        _ => {}
+    }
+}
 
-        let non_empty_key_val_iter = parsed_line_key_val_pairs
-            .map(|option| option.unwrap())
-            .filter(|(k, v)| !k.is_empty() && !v.is_empty());
+/// Pipe the generated content through a shell command, returning its output
+fn run_post_process(command: &str, content: String) -> Result<String, String> {
+    let (shell, shell_arg) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut child = std::process::Command::new(shell)
+        .args([shell_arg, command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Could not run post-process command: {}", err))?;
+
+    // write from another thread so a command producing output early cannot deadlock us
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(content.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("Could not run post-process command: {}", err))?;
+    // a command that exits without reading all its input is judged by its exit status alone
+    let _ = writer.join();
 
-        // add key value pairs to hashmap
-        env_hash_map.extend(non_empty_key_val_iter)
+    if !output.status.success() {
+        return Err(format!(
+            "Post-process command failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
     }
+    String::from_utf8(output.stdout)
+        .map_err(|_| String::from("Post-process command output is not valid UTF-8"))
+}
+
+/// Write each variable to its own file in the output directory, the file name being the key
+/// and the content the value
+fn write_env_dir(output_path: &Path, env_map: &EnvMap, mode: Option<u32>) -> std::io::Result<()> {
+    std::fs::create_dir_all(output_path)?;
+    for (k, v) in env_map.iter() {
+        let key = k.trim();
+        if key.is_empty() || key.contains(std::path::is_separator) || key == "." || key == ".." {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("key {} is not a valid file name", key),
+            ));
+        }
+        write_env_file(&output_path.join(key), v.trim(), mode)?;
+    }
+    Ok(())
+}
+
+/// Log the variables read from each yaml file and the merged result to stderr, leaving out
+/// the values if `redact` is set
+fn log_conversion(conversion: &Conversion, redact: bool) {
+    let log_values = |env_map: &EnvMap| {
+        for (k, v) in env_map.sorted_pairs() {
+            if redact {
+                eprintln!("  {}", k);
+            } else {
+                eprintln!("  {}={}", k, v);
+            }
+        }
+    };
+    for source_file in conversion.source_files.iter() {
+        eprintln!(
+            "Read {} variables from {}",
+            source_file.values.len(),
+            source_file.path.display()
+        );
+        log_values(&source_file.values);
+    }
+    for failed_path in conversion.failed_paths.iter() {
+        eprintln!("Skipped {}", failed_path.display());
+    }
+    eprintln!("Merged into {} variables", conversion.env_map.len());
+    log_values(&conversion.env_map);
+}
 
-    Ok(env_hash_map)
+/// Read the yaml file paths from the config or the positional paths, expanding globs and
+/// directories, along with the key prefixes the config declares for them
+fn resolve_yaml_paths(args: &InputArgs) -> Result<ResolvedPaths, YamlToEnvError> {
+    let config_entries = match (&args.config_path, &args.config_inline) {
+        (Some(config_path), _) => {
+            read_config_file(config_path, args.config_format, args.max_path_length)?
+        }
+        (None, Some(config_inline)) => parse_inline_config(config_inline)?,
+        // clap requires positional paths otherwise
+        (None, None) => args.paths.iter().map(ConfigEntry::new).collect(),
+    };
+    resolve_config_entries(config_entries, args.max_files)
+}
+
+/// The path of a file as reported by file system events, which may no longer exist
+fn absolute_watch_path(path: &Path) -> Option<PathBuf> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Some(parent.canonicalize().ok()?.join(path.file_name()?))
 }
 
-/// Concert hashmap to string
-fn convert_map_to_string(env_map: HashMap<String, String>) -> String {
-    let mut output_string = String::new();
-    env_map.iter().for_each(|(k, v)| {
-        let formatted_key = k.trim();
-        let formatted_value = v.trim();
-        let line = format!("{}={}\n", formatted_key, formatted_value);
-        output_string.push_str(line.as_str());
+/// The config file, the base env file and the yaml files, whose changes trigger a new
+/// conversion; URLs are left out as they have no local directory
+fn collect_watch_paths(
+    config_path: Option<&Path>,
+    base_env_path: Option<&Path>,
+    yaml_file_paths: &[PathBuf],
+) -> HashSet<PathBuf> {
+    config_path
+        .into_iter()
+        .chain(base_env_path)
+        .chain(yaml_file_paths.iter().map(PathBuf::as_path))
+        .filter_map(absolute_watch_path)
+        .collect()
+}
+
+/// Watch the parent directories of the watched files, so that files replaced by editors on
+/// save are still picked up
+fn watch_dirs(watcher: &mut RecommendedWatcher, watch_paths: &HashSet<PathBuf>) {
+    let dirs: HashSet<&Path> = watch_paths
+        .iter()
+        .filter_map(|path| path.parent())
+        .collect();
+    for dir in dirs {
+        if let Err(err) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            eprintln!("Warning: could not watch {}: {}", dir.display(), err);
+        }
+    }
+}
+
+/// Run this program again with the same arguments except `--watch`, so that a failed
+/// conversion is reported without ending the watch
+fn run_conversion() {
+    let status = std::env::current_exe().and_then(|exe| {
+        std::process::Command::new(exe)
+            .args(std::env::args_os().skip(1).filter(|arg| arg != "--watch"))
+            .status()
     });
-    output_string
+    match status {
+        Ok(status) if !status.success() => eprintln!("Conversion failed, waiting for changes"),
+        Ok(_) => {}
+        Err(err) => eprintln!("Could not run conversion: {}", err),
+    }
 }
 
-fn write_env_file(output_path: &PathBuf, output_content: &str) -> std::io::Result<()> {
-    let mut file = File::create(output_path)?;
-    file.write_all(output_content.as_bytes())?;
-    Ok(())
+/// Convert again whenever the config file, the base env file or a yaml file changes, until
+/// interrupted
+fn watch(args: &Args, cmd: &mut Command, yaml_file_paths: &[PathBuf]) -> ! {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|err| {
+            cmd.error(
+                clap::ErrorKind::Io,
+                format!("Could not start watching: {}", err),
+            )
+        })
+        .unwrap_or_else(|e| e.exit());
+    let mut watch_paths = collect_watch_paths(
+        args.input.config_path.as_deref(),
+        args.base_env_path.as_deref(),
+        yaml_file_paths,
+    );
+    watch_dirs(&mut watcher, &watch_paths);
+    let config_path = args
+        .input
+        .config_path
+        .as_deref()
+        .and_then(absolute_watch_path);
+    run_conversion();
+    eprintln!("Watching {} files for changes", watch_paths.len());
+    while let Ok(event) = receiver.recv() {
+        let mut changed_paths = HashSet::new();
+        let mut event = Some(event);
+        // editors often write a file several times on save, so wait for the events to settle
+        while let Some(result) = event {
+            if let Ok(event) = result {
+                if !event.kind.is_access() {
+                    changed_paths.extend(
+                        event
+                            .paths
+                            .iter()
+                            .filter_map(|path| absolute_watch_path(path))
+                            .filter(|path| watch_paths.contains(path)),
+                    );
+                }
+            }
+            event = receiver.recv_timeout(WATCH_DEBOUNCE).ok();
+        }
+        if changed_paths.is_empty() {
+            continue;
+        }
+        let mut changed_paths: Vec<_> = changed_paths.into_iter().collect();
+        changed_paths.sort();
+        for path in changed_paths.iter() {
+            eprintln!("Changed: {}", path.display());
+        }
+        if config_path.is_some()
+            && changed_paths
+                .iter()
+                .any(|path| Some(path) == config_path.as_ref())
+        {
+            match resolve_yaml_paths(&args.input) {
+                Ok(resolved) => {
+                    watch_paths = collect_watch_paths(
+                        args.input.config_path.as_deref(),
+                        args.base_env_path.as_deref(),
+                        &resolved.paths,
+                    );
+                    watch_dirs(&mut watcher, &watch_paths);
+                }
+                Err(err) => eprintln!("error: {}", err),
+            }
+        }
+        run_conversion();
+    }
+    std::process::exit(1)
+}
+
+/// Print the lint issues of the yaml files by category and exit, non-zero if an issue is of
+/// the error level or above
+fn run_lint(args: &LintArgs, cmd: &mut Command) -> ! {
+    let issues = resolve_yaml_paths(&args.input)
+        .and_then(|resolved| {
+            yaml_to_env::lint(&ConvertOptions::new(), &resolved.paths, args.max_nesting)
+        })
+        .map_err(|err| cmd.error(clap::ErrorKind::ValueValidation, err))
+        .unwrap_or_else(|e| e.exit());
+    let mut categories = issues
+        .iter()
+        .map(|issue| issue.category)
+        .collect::<Vec<_>>();
+    categories.sort();
+    categories.dedup();
+    let mut report = String::new();
+    for category in categories {
+        report.push_str(&format!("{} ({}):\n", category, category.level()));
+        issues
+            .iter()
+            .filter(|issue| issue.category == category)
+            .for_each(|issue| report.push_str(&format!("  {}\n", issue)));
+    }
+    report.push_str(&format!("{} issues found\n", issues.len()));
+    print_stdout(&report);
+    let failed = issues
+        .iter()
+        .any(|issue| issue.category.level() >= args.error_level);
+    std::process::exit(if failed { 1 } else { 0 })
+}
+
+/// The optional cargo features, with whether this binary was built with them
+fn compiled_features() -> [(&'static str, bool); 1] {
+    [("remote", cfg!(feature = "remote"))]
 }
 
 fn main() {
     let mut cmd: Command = Command::new("YAML to .env");
     let args = Args::parse();
-    let input_paths = read_config_file(&args.config_path);
-    let yaml_file_paths = assert_paths_are_yaml_files(input_paths, &mut cmd).unwrap();
-    let env_map = create_env_hashmap(yaml_file_paths, &mut cmd).unwrap();
-    let output_string = convert_map_to_string(env_map);
-    let res = write_env_file(&args.output_path, &output_string);
+    if let Some(Action::Lint(lint_args)) = &args.action {
+        run_lint(lint_args, &mut cmd);
+    }
+    if args.features {
+        let report = compiled_features()
+            .into_iter()
+            .map(|(feature, enabled)| {
+                let state = if enabled { "enabled" } else { "disabled" };
+                format!("{}: {}\n", feature, state)
+            })
+            .collect::<String>();
+        print_stdout(&report);
+        return;
+    }
+    let output_path = args
+        .output_path
+        .as_deref()
+        .expect("--output is required without --features");
+    if args.split_files && output_path.as_os_str() == "-" {
+        cmd.error(
+            clap::ErrorKind::ArgumentConflict,
+            "--split-files writes to a directory, not to stdout",
+        )
+        .exit();
+    }
+    let resolved = resolve_yaml_paths(&args.input)
+        .map_err(|err| cmd.error(clap::ErrorKind::ValueValidation, err))
+        .unwrap_or_else(|e| e.exit());
+    if args.watch {
+        watch(&args, &mut cmd, &resolved.paths);
+    }
+    let allowlist = args
+        .allowlist_path
+        .as_deref()
+        .map(|allowlist_path| read_allowlist_file(allowlist_path, &mut cmd))
+        .transpose()
+        .unwrap_or_else(|e| e.exit());
+    let previous_env_map = args
+        .changed_since_path
+        .as_deref()
+        .map(|previous_path| read_env_file(previous_path, &mut cmd))
+        .transpose()
+        .unwrap_or_else(|e| e.exit());
+    let base_env = args
+        .base_env_path
+        .as_deref()
+        .map(|base_env_path| {
+            read_env_file(base_env_path, &mut cmd)
+                .map(|values| SourceFile::new(base_env_path.to_path_buf(), values))
+        })
+        .transpose()
+        .unwrap_or_else(|e| e.exit());
+    let options = ConvertOptions::new()
+        .allow_multiline(args.allow_multiline)
+        .unquote_numbers(args.unquote_numbers)
+        .keep_string_quotes(args.keep_string_quotes)
+        .url_decode(args.url_decode, args.url_decode_strict)
+        .allowlist(allowlist)
+        .default_value(args.default_value.clone())
+        .key_pattern(args.grep.clone())
+        .on_parse_error(args.on_parse_error)
+        .on_conflict(args.on_conflict)
+        .output_format(args.output_format)
+        .sort(args.sort)
+        .group_by_file(args.group_by_file)
+        .cache_path(args.cache_path.clone())
+        .changed_since(previous_env_map)
+        .warn_on_trim(args.warn_on_trim)
+        .prefix_from_filename(args.prefix_from_filename)
+        .file_prefixes(resolved.file_prefixes)
+        .key_case(args.key_case)
+        .pretty(args.pretty)
+        .case_insensitive_keys(args.case_insensitive_keys)
+        .timeout(args.timeout.map(Duration::from_secs))
+        .dotenv_compat(args.dotenv_compat)
+        .quote_mode(args.quote_mode)
+        .profiles(args.profiles.clone())
+        .yaml_version(args.yaml_version)
+        .explain_key(args.explain.clone())
+        .base_env(base_env)
+        .separator(args.separator.clone())
+        .array_separator(args.array_separator.clone())
+        .max_depth(args.max_depth)
+        .array_mode(args.array_mode)
+        .env_section(args.env_section.clone())
+        .interpolate(!args.no_interpolate)
+        .allow_env_substitution(args.allow_env_substitution)
+        .lenient_yaml(args.lenient_yaml)
+        .strip_trailing_value_newline(!args.no_strip_trailing_value_newline);
+    let mut conversion = yaml_to_env::convert(&options, &resolved.paths)
+        .map_err(|msg| cmd.error(clap::ErrorKind::ValueValidation, msg))
+        .unwrap_or_else(|e| e.exit());
+    if let Some(trace) = &conversion.trace {
+        let report = trace
+            .iter()
+            .map(|step| format!("{}\n", step))
+            .collect::<String>();
+        print_stdout(&report);
+        return;
+    }
+    if args.verbose {
+        log_conversion(&conversion, !args.no_redact_log);
+    }
+    if args.stats {
+        RunSummary::new(output_path, &conversion, args.base_env_path.is_some()).print_file_stats();
+    }
+    for warning in conversion.warnings.iter() {
+        eprintln!("Warning: {}", warning);
+    }
+    if let Some(post_process) = &args.post_process {
+        conversion.output = run_post_process(post_process, conversion.output)
+            .map_err(|msg| cmd.error(clap::ErrorKind::ValueValidation, msg))
+            .unwrap_or_else(|e| e.exit());
+    }
+    if args.merge {
+        if args.output_format != OutputFormat::Env {
+            cmd.error(
+                clap::ErrorKind::ArgumentConflict,
+                "--merge only supports the env output format",
+            )
+            .exit();
+        }
+        let existing = std::fs::read_to_string(output_path).unwrap_or_default();
+        conversion.output = merge_env_str(&existing, &conversion.output)
+            .map_err(|msg| cmd.error(clap::ErrorKind::ValueValidation, msg))
+            .unwrap_or_else(|e| e.exit());
+    }
+    if args.check {
+        let up_to_date = check_output_file(output_path, &conversion.output, args.output_format);
+        std::process::exit(if up_to_date { 0 } else { 1 });
+    }
+    let writes_to_stdout = output_path.as_os_str() == "-";
+    if args.chmod.is_some() && !cfg!(unix) {
+        eprintln!("Warning: --chmod is ignored on this platform");
+    }
+    let res = if args.split_files {
+        write_env_dir(output_path, &conversion.env_map, args.chmod)
+    } else if writes_to_stdout {
+        write_stdout(&conversion.output)
+    } else {
+        write_env_file(output_path, &conversion.output, args.chmod)
+    };
     match res {
-        Ok(_) => println!("Env file created succesfully."),
-        Err(err) => println!("Error when trying to write env file: {}", err),
+        // the reader went away, e.g. `head`, which is not our failure
+        Err(err) if writes_to_stdout && err.kind() == std::io::ErrorKind::BrokenPipe => {
+            std::process::exit(0)
+        }
+        // keep stdout for the env content
+        Ok(_) if args.summary_json && writes_to_stdout => {
+            let summary = RunSummary::new(output_path, &conversion, args.base_env_path.is_some());
+            eprintln!("{}", serde_json::to_string(&summary).unwrap());
+        }
+        Ok(_) if args.summary_json => {
+            let summary = RunSummary::new(output_path, &conversion, args.base_env_path.is_some());
+            print_stdout(&format!("{}\n", serde_json::to_string(&summary).unwrap()));
+        }
+        Ok(_) if writes_to_stdout => {}
+        Ok(_) => print_stdout("Env file created succesfully.\n"),
+        Err(err) => {
+            eprintln!("Error when trying to write env file: {}", err);
+            std::process::exit(1);
+        }
+    }
+    if !conversion.failed_paths.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watches_the_config_the_base_env_and_the_yaml_files() {
+        let dir = std::env::temp_dir().join(format!("yaml-to-env-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let config_path = dir.join("files.txt");
+        let base_env_path = dir.join("base.env");
+        let yaml_file_paths = vec![
+            dir.join("a.yaml"),
+            dir.join(".").join("a.yaml"),
+            PathBuf::from("https://example.com/b.yaml"),
+        ];
+
+        let watch_paths =
+            collect_watch_paths(Some(&config_path), Some(&base_env_path), &yaml_file_paths);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            watch_paths,
+            HashSet::from([config_path, base_env_path, dir.join("a.yaml")])
+        );
     }
 }
@@ -0,0 +1,67 @@
+use crate::YamlToEnvError;
+use std::time::Duration;
+
+/// Fetch the content of a yaml file served over HTTP, within `timeout` if given and the
+/// default 30 seconds of the client otherwise
+pub fn fetch(url: &str, timeout: Option<Duration>) -> Result<String, YamlToEnvError> {
+    let unfetchable_url = |err: reqwest::Error| YamlToEnvError::UnfetchableUrl {
+        url: String::from(url),
+        reason: err.to_string(),
+    };
+    let mut builder = reqwest::blocking::Client::builder();
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    let client = builder.build().map_err(unfetchable_url)?;
+    client
+        .get(url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .map_err(unfetchable_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ConvertOptions;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    #[test]
+    fn reads_a_yaml_file_served_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/app.yaml", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let body = "name: app\nport: 80\n";
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        let parsed = crate::parse(&ConvertOptions::new(), &[PathBuf::from(&url)]).unwrap();
+        server.join().unwrap();
+        assert_eq!(parsed.env_map.to_string(), "name=app\nport=80\n");
+    }
+
+    #[test]
+    fn names_the_url_that_could_not_be_fetched() {
+        // nothing listens on the port once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/app.yaml", listener.local_addr().unwrap());
+        drop(listener);
+
+        let err = crate::parse(&ConvertOptions::new(), &[PathBuf::from(&url)]).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::YamlToEnvError::UnfetchableUrl { url: failed_url, .. } if failed_url == url
+        ));
+    }
+}
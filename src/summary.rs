@@ -0,0 +1,89 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use yaml_to_env::Conversion;
+
+/// Number of variables read from a single yaml file, how many of them a later file overrode
+/// and how long reading it took
+#[derive(Serialize, Debug)]
+pub struct FileSummary {
+    path: PathBuf,
+    variable_count: usize,
+    overridden_count: usize,
+    parse_ms: f64,
+}
+
+/// Machine readable result of a run, printed with `--summary-json`
+#[derive(Serialize, Debug)]
+pub struct RunSummary {
+    output_path: PathBuf,
+    variable_count: usize,
+    files: Vec<FileSummary>,
+    conflicts_resolved: usize,
+    warnings: Vec<String>,
+}
+
+/// The index of the file whose value of a key the merge kept, matching keys that were merged
+/// regardless of case too
+fn final_origin(origins: &HashMap<String, usize>, key: &str) -> Option<usize> {
+    origins.get(key).copied().or_else(|| {
+        origins
+            .iter()
+            .find(|(k, _)| k.trim().to_uppercase() == key.trim().to_uppercase())
+            .map(|(_, origin)| *origin)
+    })
+}
+
+impl RunSummary {
+    /// Summarize a conversion, whose first source file is the base env if `has_base_env` is set
+    pub fn new(output_path: &Path, conversion: &Conversion, has_base_env: bool) -> Self {
+        let source_files = &conversion.source_files;
+        let origins = &conversion.origins;
+        // a conflict is a value of a yaml file that the merge dropped for the value of another
+        let conflicts_resolved = source_files
+            .iter()
+            .enumerate()
+            .skip(usize::from(has_base_env))
+            .flat_map(|(i, source_file)| source_file.values.iter().map(move |(k, _)| (i, k)))
+            .filter(|(i, k)| final_origin(origins, k) != Some(*i))
+            .count();
+
+        Self {
+            output_path: output_path.to_path_buf(),
+            variable_count: conversion.env_map.len(),
+            files: source_files
+                .iter()
+                .enumerate()
+                .map(|(i, source_file)| FileSummary {
+                    path: source_file.path.clone(),
+                    variable_count: source_file.values.len(),
+                    overridden_count: source_file
+                        .values
+                        .iter()
+                        .filter(|(k, _)| final_origin(origins, k).is_some_and(|origin| origin > i))
+                        .count(),
+                    parse_ms: source_file.parse_time.as_secs_f64() * 1000.0,
+                })
+                .collect(),
+            conflicts_resolved,
+            warnings: conversion
+                .warnings
+                .iter()
+                .map(|warning| warning.to_string())
+                .collect(),
+        }
+    }
+
+    /// Print the statistics of each file to stderr, for `--stats`
+    pub fn print_file_stats(&self) {
+        for file in self.files.iter() {
+            eprintln!(
+                "{}: {} variables, {} overridden by later files, parsed in {:.2} ms",
+                file.path.display(),
+                file.variable_count,
+                file.overridden_count,
+                file.parse_ms
+            );
+        }
+    }
+}
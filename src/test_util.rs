@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of temporary directories created by this test process
+static DIR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory for the files of a test, removed when dropped
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create an empty directory unique to this test process
+    pub(crate) fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "yaml-to-env-unit-{}-{}",
+            std::process::id(),
+            DIR_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    /// Write a file in the directory, returning its path
+    pub(crate) fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Non-fatal problem found during a conversion. The library never prints these; they are
+/// returned in `Conversion::warnings` for the caller to surface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A yaml file could not be read or parsed and was skipped
+    SkippedFile { path: PathBuf, reason: String },
+    /// A yaml file did not parse and was read with the line parser instead
+    LenientYaml { path: PathBuf, reason: String },
+    /// The cache file could not be read, so every file was parsed
+    UnreadableCache { reason: String },
+    /// The cache file could not be written
+    UnwritableCache { reason: String },
+    /// A value contains newlines, which break most .env parsers
+    MultilineValue { key: String },
+    /// Whitespace around a key is dropped on output
+    TrimmedKey { key: String },
+    /// Whitespace around a value is dropped on output
+    TrimmedValue { key: String },
+    /// An allowlisted key is missing from the yaml files and was given the default value
+    DefaultedKey { key: String },
+    /// Two yaml files set the same key and the later one won, the files being described as
+    /// `path:line`
+    ConflictingKey {
+        key: String,
+        first: String,
+        second: String,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::SkippedFile { reason, .. } => write!(f, "skipping file: {}", reason),
+            Warning::LenientYaml { path, reason } => write!(
+                f,
+                "reading {} line by line as it is not valid yaml: {}",
+                path.display(),
+                reason
+            ),
+            Warning::UnreadableCache { reason } => {
+                write!(f, "ignoring unreadable cache file: {}", reason)
+            }
+            Warning::UnwritableCache { reason } => {
+                write!(f, "could not write cache file: {}", reason)
+            }
+            Warning::MultilineValue { key } => {
+                write!(f, "value of key '{}' spans multiple lines", key)
+            }
+            Warning::TrimmedKey { key } => write!(f, "whitespace around key '{}' was trimmed", key),
+            Warning::TrimmedValue { key } => {
+                write!(f, "whitespace around value of key '{}' was trimmed", key)
+            }
+            Warning::DefaultedKey { key } => {
+                write!(f, "key '{}' is not set, using the default value", key)
+            }
+            Warning::ConflictingKey { key, first, second } => write!(
+                f,
+                "key '{}' set by {} is overridden by {}",
+                key, first, second
+            ),
+        }
+    }
+}
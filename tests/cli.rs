@@ -0,0 +1,344 @@
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of temporary directories created by this test process
+static DIR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory the command runs in, removed when dropped
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create an empty directory unique to this test process
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "yaml-to-env-cli-{}-{}",
+            std::process::id(),
+            DIR_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    /// Write a file in the directory, returning its path
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// Read a file of the directory
+    fn read(&self, name: &str) -> String {
+        std::fs::read_to_string(self.path.join(name)).unwrap()
+    }
+
+    /// Run the command in the directory with these arguments
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_yaml-to-env"))
+            .current_dir(&self.path)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The standard output of a run, which must have succeeded
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn prints_a_json_summary_of_the_run() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "host: a\nport: 1\n");
+    dir.write("b.yaml", "host: b\n");
+
+    let output = dir.run(&["a.yaml", "b.yaml", "-o", ".env", "--summary-json"]);
+    let summary = serde_json::from_str::<serde_json::Value>(&stdout(&output)).unwrap();
+    assert_eq!(summary["output_path"], ".env");
+    assert_eq!(summary["variable_count"], 2);
+    assert_eq!(summary["conflicts_resolved"], 1);
+    assert_eq!(summary["files"][0]["path"], "a.yaml");
+    assert_eq!(summary["files"][0]["variable_count"], 2);
+    assert_eq!(summary["files"][0]["overridden_count"], 1);
+    assert_eq!(summary["files"][1]["variable_count"], 1);
+    assert_eq!(summary["files"][1]["overridden_count"], 0);
+    assert_eq!(summary["warnings"], serde_json::json!([]));
+    assert_eq!(dir.read(".env"), "host=b\nport=1\n");
+}
+
+#[test]
+fn writes_the_files_that_parsed_and_fails_if_asked_to_continue() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "name: app\n");
+    dir.write("bad.yaml", "name: [unclosed\n");
+    dir.write("b.yaml", "port: 80\n");
+
+    let output = dir.run(&[
+        "a.yaml",
+        "bad.yaml",
+        "b.yaml",
+        "-o",
+        ".env",
+        "--on-parse-error",
+        "continue",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bad.yaml"));
+    assert_eq!(dir.read(".env"), "name=app\nport=80\n");
+}
+
+#[test]
+fn writes_one_file_per_key_when_splitting_files() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "DB_HOST: localhost\nTOKEN: \" secret \"\n");
+
+    let output = dir.run(&["a.yaml", "-o", "env.d", "--split-files"]);
+    stdout(&output);
+    assert_eq!(dir.read("env.d/DB_HOST"), "localhost");
+    assert_eq!(dir.read("env.d/TOKEN"), "secret");
+}
+
+#[cfg(unix)]
+#[test]
+fn writes_the_output_of_the_post_process_command() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "name: app\n");
+
+    let output = dir.run(&["a.yaml", "-o", ".env", "--post-process", "cat"]);
+    stdout(&output);
+    assert_eq!(dir.read(".env"), "name=app\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn fails_with_the_error_of_a_failing_post_process_command() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "name: app\n");
+
+    let output = dir.run(&[
+        "a.yaml",
+        "-o",
+        ".env",
+        "--post-process",
+        "echo no key >&2; exit 3",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no key"));
+    assert!(!dir.path.join(".env").exists());
+}
+
+#[test]
+fn reports_the_features_compiled_in() {
+    let dir = TempDir::new();
+
+    let output = dir.run(&["--features"]);
+    let expected = if cfg!(feature = "remote") {
+        "remote: enabled\n"
+    } else {
+        "remote: disabled\n"
+    };
+    assert_eq!(stdout(&output), expected);
+}
+
+#[test]
+fn reads_the_files_of_an_inline_config() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "name: app\nport: 80\n");
+    dir.write("b.yaml", "port: 8080\n");
+
+    let output = dir.run(&["--config-inline", "a.yaml, b.yaml", "-o", ".env"]);
+    stdout(&output);
+    assert_eq!(dir.read(".env"), "name=app\nport=8080\n");
+}
+
+#[test]
+fn keeps_values_out_of_the_verbose_log() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "API_TOKEN: hunter2-secret\n");
+
+    let output = dir.run(&["a.yaml", "-o", ".env", "--verbose"]);
+    stdout(&output);
+    let log = String::from_utf8(output.stderr).unwrap();
+    assert!(log.contains("API_TOKEN"));
+    assert!(!log.contains("hunter2-secret"));
+
+    let output = dir.run(&["a.yaml", "-o", ".env", "--verbose", "--no-redact-log"]);
+    stdout(&output);
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("API_TOKEN=hunter2-secret"));
+}
+
+#[test]
+fn fails_the_check_of_an_outdated_env_file_without_writing_it() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "a: 1\nb: 2\n");
+    dir.write(".env", "a=0\nb=2\n");
+
+    let output = dir.run(&["a.yaml", "-o", ".env", "--check"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "-a=0\n+a=1\n");
+    assert_eq!(dir.read(".env"), "a=0\nb=2\n");
+
+    dir.write(".env", "a=1\nb=2\n");
+    let output = dir.run(&["a.yaml", "-o", ".env", "--check"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn reports_the_variables_of_each_file() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "host: a\nport: 1\nname: x\n");
+    dir.write("b.yaml", "host: b\ndebug: true\n");
+
+    let output = dir.run(&["a.yaml", "b.yaml", "-o", ".env", "--stats"]);
+    stdout(&output);
+    let stats = String::from_utf8(output.stderr).unwrap();
+    let lines = stats.lines().collect::<Vec<&str>>();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("a.yaml: 3 variables, 1 overridden by later files, parsed in "));
+    assert!(lines[1].starts_with("b.yaml: 2 variables, 0 overridden by later files, parsed in "));
+    assert!(lines.iter().all(|line| line.ends_with(" ms")));
+}
+
+#[test]
+fn counts_only_the_values_the_merge_replaced_as_overridden() {
+    let dir = TempDir::new();
+    dir.write("f1.yaml", "a: 1\nb: 2\n");
+    dir.write("f2.yaml", "a: 9\n");
+
+    let output = dir.run(&[
+        "f1.yaml",
+        "f2.yaml",
+        "-o",
+        ".env",
+        "--on-conflict",
+        "first-wins",
+        "--summary-json",
+    ]);
+    let summary = serde_json::from_str::<serde_json::Value>(&stdout(&output)).unwrap();
+    assert_eq!(summary["conflicts_resolved"], 1);
+    assert_eq!(summary["files"][0]["overridden_count"], 0);
+    assert_eq!(summary["files"][1]["overridden_count"], 0);
+    assert_eq!(dir.read(".env"), "a=1\nb=2\n");
+
+    dir.write("base.env", "a=0\n");
+    let output = dir.run(&[
+        "f2.yaml",
+        "-o",
+        ".env",
+        "--base-env",
+        "base.env",
+        "--summary-json",
+    ]);
+    let summary = serde_json::from_str::<serde_json::Value>(&stdout(&output)).unwrap();
+    assert_eq!(summary["conflicts_resolved"], 0);
+    assert_eq!(summary["files"][0]["overridden_count"], 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn exits_cleanly_when_the_reader_of_stdout_goes_away() {
+    use std::io::Read;
+    let dir = TempDir::new();
+    // more than a pipe holds, so the writes outlast the reader
+    let yaml = (0..20_000)
+        .map(|i| format!("KEY_{}: value {}\n", i, i))
+        .collect::<String>();
+    dir.write("a.yaml", &yaml);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yaml-to-env"))
+        .current_dir(&dir.path)
+        .args(["a.yaml", "-o", "-"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut head = [0; 16];
+    child.stdout.take().unwrap().read_exact(&mut head).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn fails_the_check_without_a_panic_when_the_reader_of_stdout_goes_away() {
+    use std::io::Read;
+    let dir = TempDir::new();
+    let yaml = (0..20_000)
+        .map(|i| format!("KEY_{}: value {}\n", i, i))
+        .collect::<String>();
+    dir.write("a.yaml", &yaml);
+    dir.write(".env", "");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yaml-to-env"))
+        .current_dir(&dir.path)
+        .args(["a.yaml", "-o", ".env", "--check"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut head = [0; 16];
+    child.stdout.take().unwrap().read_exact(&mut head).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stderr.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn sets_the_mode_of_the_env_file() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new();
+    dir.write("a.yaml", "TOKEN: secret\n");
+
+    let output = dir.run(&["a.yaml", "-o", ".env", "--chmod", "600"]);
+    stdout(&output);
+    let mode = std::fs::metadata(dir.path.join(".env"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn fails_lint_only_on_issues_at_the_error_level() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "first: same\nsecond: same\ntoken: \"abc \"\n");
+
+    let output = dir.run(&["lint", "a.yaml"]);
+    assert_eq!(
+        stdout(&output),
+        "trailing whitespace (warning):\n  a.yaml: token: value ends with whitespace\n\
+         duplicate values (info):\n  a.yaml: second: same value as first in a.yaml\n\
+         2 issues found\n"
+    );
+
+    let output = dir.run(&["lint", "a.yaml", "--error-level", "warning"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn rejects_stdout_as_the_directory_of_split_files() {
+    let dir = TempDir::new();
+    dir.write("a.yaml", "name: app\n");
+
+    let output = dir.run(&["a.yaml", "-o", "-", "--split-files"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--split-files"));
+    assert!(!dir.path.join("-").exists());
+}